@@ -1,27 +1,123 @@
+use bech32::{ToBase32, Variant};
 use cosmwasm_std::{
-    entry_point, from_binary, to_binary, Addr, Binary, Deps, DepsMut, Env, IbcMsg, MessageInfo,
-    Response, StdResult, SubMsg, Uint128,
+    entry_point, from_binary, to_binary, Addr, Binary, Deps, DepsMut, Env, Event, IbcMsg,
+    MessageInfo, Order, Response, StdError, StdResult, Storage, SubMsg, Timestamp, Uint128,
+    WasmMsg,
 };
 
+use crate::decoys::{record_recent_user, validate_decoy_count};
 use crate::error::ContractError;
-use crate::ibc::Ics20Packet;
-use crate::msg::{ExecuteMsg, InitMsg, QueryMsg, Snip20Data, Snip20ReceiveMsg, TransferMsg};
+use crate::fees::{accrue_fee, fee_schedule, hold_packet_fee, take_accrued_fee};
+use crate::history::{history_page, record_history};
+use crate::ibc::{
+    packet_key, redact_attributes, transfer_amount, Ics20Packet, REGISTER_ID_OFFSET,
+    RELEASE_ID_OFFSET, VIEWING_KEY_ID_OFFSET,
+};
+use crate::msg::{
+    BatchStatusResponse, ChainsResponse, ChannelAllowListResponse, ChannelHeadroom,
+    ChannelInfoResponse, ChannelMigrationsResponse, ChannelStateResponse, EscrowHeadroomResponse,
+    ExecuteMsg, InitMsg, ListChannelsResponse, ListTokensResponse, QueryMsg, QueryWithPermit,
+    ReconcileResponse, RegisteredChain, RegisteredToken, RelayerReward, RelayerStatsResponse,
+    RemainingQuotaResponse, SenderMode, Snip20Data, Snip20ReceiveMsg, TransferHistoryResponse,
+    TransferMsg, ViewerInfo, ViewingKeyResponse,
+};
+use secret_toolkit::permit::TokenPermissions;
 use secret_toolkit::snip20;
+use secret_toolkit::utils::{pad_handle_result, pad_query_result};
+use sha2::{Digest, Sha256};
 
-use crate::state::{increase_channel_balance, CHANNEL_INFO, CODE_HASH};
+use crate::notifications::{
+    channel_info, init_notification_secret, notification_seed, TRANSFER_STATUS_CHANNEL,
+};
+use crate::padding::{pad_binary, DEFAULT_BLOCK_SIZE};
+use crate::permit::{revoke_permit, validate_permit};
+use crate::rate_limit::{
+    address_quota_status, check_and_update_address_quota, check_and_update_flow, FlowType,
+};
+use crate::reconcile::reconcile;
+use crate::registry::{address_prefix, registered_chain_id, resolve_target};
+use crate::state::{
+    channel_escrow_cap, hold_packet_sender, increase_channel_balance, migrate_channel_balances,
+    total_outstanding, AddressQuota, ChainInfo, Config, EscrowCap, FeeConfig, FeeSchedule,
+    HistoryAction, MovedBalance, PauseState, PendingMigration, QueuedTransfer, Quota,
+    QuotaOverride, RateLimit, RelayerIncentive, StatsVisibility, TokenRoute, TokenStatus,
+    ACCRUED_FEES, ADDRESS_QUOTAS, ADDRESS_QUOTA_OVERRIDES, ALLOWED_CONNECTIONS,
+    ALLOWED_COUNTERPARTY_PORTS, CHAIN_REGISTRY, CHANNEL_CHAINS, CHANNEL_ESCROW_CAPS, CHANNEL_INFO,
+    CHANNEL_STATE, CODE_HASH, CONFIG, DECOY_POOL, EPOCH_END, ESCROW_CAPS, FAILED_RELEASES,
+    MIGRATION_LOG, NEXT_QUEUE_ID, PENDING_MIGRATIONS, PENDING_REGISTRATIONS, PENDING_VIEWING_KEYS,
+    PRIVATE_STATS, QUEUED_TRANSFERS, RATE_LIMITS, RELAYER_REWARDS, RELAYER_STATS, RETIRED_CHANNELS,
+    TOKEN_DECOYS, TOKEN_DETAILS, TOKEN_FEES, TOKEN_ROUTES,
+};
+use crate::stats::{reveal, reveal_rate_limit, reveal_reconcile, stats_access, StatsAccess};
+use crate::tokens::{assert_token_active, query_token_details};
+use crate::viewing_key::{
+    bridge_viewing_key, check_user_viewing_key, new_bridge_viewing_key, new_user_viewing_key,
+    next_seed, set_user_viewing_key,
+};
 
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn instantiate(
-    mut _deps: DepsMut,
-    _env: Env,
-    _info: MessageInfo,
-    _msg: InitMsg,
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    msg: InitMsg,
 ) -> Result<Response, ContractError> {
+    let entropy = [
+        msg.entropy.unwrap_or_default().as_bytes(),
+        info.sender.as_bytes(),
+    ]
+    .concat();
+    new_bridge_viewing_key(deps.storage, &env, &entropy)?;
+    init_notification_secret(deps.storage, &env, &entropy)?;
+
+    let admin = match msg.admin {
+        Some(admin) => deps.api.addr_validate(&admin)?,
+        None => info.sender,
+    };
+    let pauser = msg
+        .pauser
+        .map(|pauser| deps.api.addr_validate(&pauser))
+        .transpose()?;
+    let config = Config {
+        admin,
+        pauser,
+        paused: PauseState::default(),
+        fees: FeeConfig::default(),
+        relayer_incentive: RelayerIncentive::default(),
+        escrow_guard: false,
+        private_events: false,
+        block_size: msg.block_size.unwrap_or(DEFAULT_BLOCK_SIZE),
+        batch_epoch: None,
+    };
+    if config.block_size == 0 {
+        return Err(ContractError::InvalidBlockSize {});
+    }
+    CONFIG.save(deps.storage, &config)?;
+
+    for connection in &msg.allowed_connections {
+        ALLOWED_CONNECTIONS.save(deps.storage, connection, &true)?;
+    }
+    for port in &msg.allowed_counterparty_ports {
+        ALLOWED_COUNTERPARTY_PORTS.save(deps.storage, port, &true)?;
+    }
+
     Ok(Response::default())
 }
 
 #[entry_point]
 pub fn execute(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    msg: ExecuteMsg,
+) -> Result<Response, ContractError> {
+    let res = dispatch(deps.branch(), env, info, msg);
+    // loaded after handling the message, which may have changed the block size
+    let block_size = CONFIG.load(deps.storage)?.block_size;
+    pad_handle_result(res, block_size.into())
+}
+
+fn dispatch(
     deps: DepsMut,
     env: Env,
     info: MessageInfo,
@@ -29,11 +125,156 @@ pub fn execute(
 ) -> Result<Response, ContractError> {
     match msg {
         ExecuteMsg::Receive(msg) => execute_receive(deps, env, info, msg),
-        ExecuteMsg::RegisterTokens { tokens } => {
-            let output_msgs = register_tokens(deps, env, tokens)?;
+        ExecuteMsg::RegisterTokens { tokens, .. } => {
+            let output_msgs = register_tokens(deps, env, info, tokens)?;
 
             Ok(Response::new().add_submessages(output_msgs))
         }
+        ExecuteMsg::UpdateTokenCodeHash {
+            token, code_hash, ..
+        } => execute_update_token_code_hash(deps, info, token, code_hash),
+        ExecuteMsg::UpdateAdmin { admin, .. } => execute_update_admin(deps, info, admin),
+        ExecuteMsg::SetPauser { pauser, .. } => execute_set_pauser(deps, info, pauser),
+        ExecuteMsg::Pause {
+            outbound,
+            inbound,
+            refunds,
+            ..
+        } => execute_pause(deps, info, outbound, inbound, refunds),
+        ExecuteMsg::Unpause {
+            outbound,
+            inbound,
+            refunds,
+            ..
+        } => execute_unpause(deps, info, outbound, inbound, refunds),
+        ExecuteMsg::SetRateLimit {
+            channel,
+            token,
+            quota,
+            ..
+        } => execute_set_rate_limit(deps, info, channel, token, quota),
+        ExecuteMsg::RemoveRateLimit {
+            channel,
+            token,
+            name,
+            ..
+        } => execute_remove_rate_limit(deps, info, channel, token, name),
+        ExecuteMsg::ResetRateLimit {
+            channel,
+            token,
+            name,
+            ..
+        } => execute_reset_rate_limit(deps, info, channel, token, name),
+        ExecuteMsg::SetAddressQuota { token, quota, .. } => {
+            execute_set_address_quota(deps, info, token, quota)
+        }
+        ExecuteMsg::SetQuotaOverride {
+            token,
+            address,
+            max_amount,
+            ..
+        } => execute_set_quota_override(deps, info, token, address, max_amount),
+        ExecuteMsg::RemoveQuotaOverride { token, address, .. } => {
+            execute_remove_quota_override(deps, info, token, address)
+        }
+        ExecuteMsg::SetEscrowCap { token, cap, .. } => {
+            execute_set_escrow_cap(deps, info, token, cap)
+        }
+        ExecuteMsg::SetChannelEscrowCap {
+            channel,
+            token,
+            cap,
+            ..
+        } => execute_set_channel_escrow_cap(deps, info, channel, token, cap),
+        ExecuteMsg::SetFees {
+            default,
+            collector,
+            accrue,
+            refund_fee,
+            ..
+        } => execute_set_fees(deps, info, default, collector, accrue, refund_fee),
+        ExecuteMsg::SetTokenFee {
+            token, schedule, ..
+        } => execute_set_token_fee(deps, info, token, schedule),
+        ExecuteMsg::ClaimFees { token, amount, .. } => {
+            execute_claim_fees(deps, info, token, amount)
+        }
+        ExecuteMsg::SetRelayerIncentive {
+            fee_share_bps,
+            flat,
+            ..
+        } => execute_set_relayer_incentive(deps, info, fee_share_bps, flat),
+        ExecuteMsg::ClaimRelayerRewards { token, .. } => {
+            execute_claim_relayer_rewards(deps, info, token)
+        }
+        ExecuteMsg::UpdateChannelAllowList {
+            add_connections,
+            remove_connections,
+            add_counterparty_ports,
+            remove_counterparty_ports,
+            ..
+        } => execute_update_channel_allow_list(
+            deps,
+            info,
+            add_connections,
+            remove_connections,
+            add_counterparty_ports,
+            remove_counterparty_ports,
+        ),
+        ExecuteMsg::SetTokenRoute { token, route, .. } => {
+            execute_set_token_route(deps, info, token, route)
+        }
+        ExecuteMsg::SetChain {
+            chain,
+            info: chain_info,
+            ..
+        } => execute_set_chain(deps, info, chain, chain_info),
+        ExecuteMsg::MigrateChannel {
+            channel,
+            successor,
+            delay,
+            ..
+        } => execute_migrate_channel(deps, env, info, channel, successor, delay),
+        ExecuteMsg::CompleteChannelMigration { channel, .. } => {
+            execute_complete_channel_migration(deps, env, info, channel)
+        }
+        ExecuteMsg::CancelChannelMigration { channel, .. } => {
+            execute_cancel_channel_migration(deps, info, channel)
+        }
+        ExecuteMsg::SetEscrowGuard { enabled, .. } => execute_set_escrow_guard(deps, info, enabled),
+        ExecuteMsg::Sweep {
+            token,
+            recipient,
+            amount,
+            ..
+        } => execute_sweep(deps, env, info, token, recipient, amount),
+        ExecuteMsg::RotateViewingKeys { entropy, .. } => {
+            execute_rotate_viewing_keys(deps, env, info, entropy)
+        }
+        ExecuteMsg::SetViewingKey { key, .. } => execute_set_viewing_key(deps, info, key),
+        ExecuteMsg::CreateViewingKey { entropy, .. } => {
+            execute_create_viewing_key(deps, env, info, entropy)
+        }
+        ExecuteMsg::RevokePermit { permit_name, .. } => {
+            execute_revoke_permit(deps, info, permit_name)
+        }
+        ExecuteMsg::SetPrivateEvents { enabled, .. } => {
+            execute_set_private_events(deps, info, enabled)
+        }
+        ExecuteMsg::SetBlockSize { block_size, .. } => {
+            execute_set_block_size(deps, info, block_size)
+        }
+        ExecuteMsg::SetBatching { epoch, .. } => execute_set_batching(deps, env, info, epoch),
+        ExecuteMsg::Flush { limit, .. } => execute_flush(deps, env, limit),
+        ExecuteMsg::CancelTransfer { id, .. } => execute_cancel_transfer(deps, info, id),
+        ExecuteMsg::ReleaseTransfer { id, .. } => execute_release_transfer(deps, env, info, id),
+        ExecuteMsg::SetTokenDecoys { token, count, .. } => {
+            execute_set_token_decoys(deps, info, token, count)
+        }
+        ExecuteMsg::SetDecoyPool { addresses, .. } => execute_set_decoy_pool(deps, info, addresses),
+        ExecuteMsg::SetStatsVisibility {
+            token, visibility, ..
+        } => execute_set_stats_visibility(deps, info, token, visibility),
     }
 }
 
@@ -50,17 +291,231 @@ pub fn execute_receive(
         return Err(ContractError::MissingTransferMsg {});
     }
 
+    // any contract can call Receive, only registered tokens may be sent or queued
+    if !CODE_HASH.has(deps.storage, info.sender.clone()) {
+        return Err(ContractError::UnregisteredAsset {
+            address: info.sender,
+        });
+    }
+    assert_token_active(deps.storage, &info.sender)?;
+
     let api = deps.api;
+    let sender = api.addr_validate(&wrapper.sender)?;
+    match CONFIG.load(deps.storage)?.batch_epoch {
+        Some(epoch) => queue_transfer(
+            deps,
+            env,
+            transfer_msg,
+            info.sender,
+            wrapper.amount,
+            sender,
+            epoch,
+        ),
+        None => execute_ibc_transfer(deps, env, transfer_msg, info.sender, wrapper.amount, sender),
+    }
+}
+
+fn next_epoch_end(now: Timestamp, epoch: u64) -> Timestamp {
+    Timestamp::from_seconds((now.seconds() / epoch + 1) * epoch)
+}
+
+// the checks run when the transfer is released, a failing one is refunded then
+fn queue_transfer(
+    deps: DepsMut,
+    env: Env,
+    msg: TransferMsg,
+    token: Addr,
+    amount: Uint128,
+    sender: Addr,
+    epoch: u64,
+) -> Result<Response, ContractError> {
+    if amount.is_zero() {
+        return Err(ContractError::NoFunds {});
+    }
+    let config = CONFIG.load(deps.storage)?;
+    if config.paused.outbound {
+        return Err(ContractError::Paused {
+            direction: "outbound".into(),
+        });
+    }
+    // dust would only be rejected on release, after taking up a place in the queue
+    fee_schedule(deps.storage, &config.fees, token.as_str())?.fee_for(amount)?;
+
+    // the first transfer after the epoch ends releases the queue
+    let mut res = Response::new();
+    let epoch_over = match EPOCH_END.may_load(deps.storage)? {
+        Some(end) => env.block.time >= end,
+        None => true,
+    };
+    if epoch_over {
+        let (releases, drained) =
+            release_queue(deps.storage, &env, config.block_size, MAX_RELEASES)?;
+        res = res.add_submessages(releases);
+        if drained {
+            EPOCH_END.save(deps.storage, &next_epoch_end(env.block.time, epoch))?;
+        }
+    }
+
+    let id = NEXT_QUEUE_ID.may_load(deps.storage)?.unwrap_or_default();
+    NEXT_QUEUE_ID.save(deps.storage, &(id + 1))?;
+    let queued = QueuedTransfer {
+        msg,
+        token,
+        amount,
+        sender,
+    };
+    QUEUED_TRANSFERS.save(deps.storage, id, &queued)?;
+
+    Ok(res
+        .set_data(to_binary(&id)?)
+        .add_attribute("action", "queue_transfer")
+        .add_attribute("queue_id", id.to_string()))
+}
+
+/// Most queued transfers released at once, so a flush stays within the block gas limit
+const MAX_RELEASES: u32 = 32;
+
+// every queued transfer is released in its own submessage, so one failing is refunded alone.
+// Releases the oldest `limit` transfers, returning whether that drains the queue.
+fn release_queue(
+    storage: &dyn Storage,
+    env: &Env,
+    block_size: u16,
+    limit: u32,
+) -> StdResult<(Vec<SubMsg>, bool)> {
+    let mut ids: Vec<u64> = QUEUED_TRANSFERS
+        .keys(storage, None, None, Order::Ascending)
+        .take(limit as usize + 1)
+        .collect::<StdResult<_>>()?;
+    let drained = ids.len() <= limit as usize;
+    ids.truncate(limit as usize);
+    let releases = ids
+        .into_iter()
+        .map(|id| {
+            let release = WasmMsg::Execute {
+                contract_addr: env.contract.address.to_string(),
+                code_hash: env.contract.code_hash.clone(),
+                msg: pad_binary(
+                    to_binary(&ExecuteMsg::ReleaseTransfer { id, padding: None })?,
+                    block_size,
+                ),
+                funds: vec![],
+            };
+            Ok(SubMsg::reply_on_error(release, RELEASE_ID_OFFSET + id))
+        })
+        .collect::<StdResult<_>>()?;
+    Ok((releases, drained))
+}
+
+pub fn execute_flush(
+    deps: DepsMut,
+    env: Env,
+    limit: Option<u32>,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if config.paused.outbound {
+        return Err(ContractError::Paused {
+            direction: "outbound".into(),
+        });
+    }
+    // with batching turned off, whatever is left in the queue can go right away
+    if let Some(ends) = EPOCH_END.may_load(deps.storage)? {
+        if config.batch_epoch.is_some() && env.block.time < ends {
+            return Err(ContractError::EpochNotOver { ends });
+        }
+    }
+
+    let limit = limit.unwrap_or(MAX_RELEASES).min(MAX_RELEASES);
+    let (releases, drained) = release_queue(deps.storage, &env, config.block_size, limit)?;
+    // the epoch only moves on once the queue is empty, so flushing can go on until it is
+    if let (Some(epoch), true) = (config.batch_epoch, drained) {
+        EPOCH_END.save(deps.storage, &next_epoch_end(env.block.time, epoch))?;
+    }
+    Ok(Response::new()
+        .add_attribute("action", "flush")
+        .add_attribute("released", releases.len().to_string())
+        .add_attribute("drained", drained.to_string())
+        .add_submessages(releases))
+}
+
+pub fn execute_release_transfer(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    id: u64,
+) -> Result<Response, ContractError> {
+    if info.sender != env.contract.address {
+        return Err(ContractError::Unauthorized);
+    }
+    let queued = QUEUED_TRANSFERS
+        .may_load(deps.storage, id)?
+        .ok_or(ContractError::NoSuchQueuedTransfer { id })?;
+    // removed first, so the escrow guard no longer counts it as queued
+    QUEUED_TRANSFERS.remove(deps.storage, id);
+
     execute_ibc_transfer(
         deps,
         env,
-        transfer_msg,
-        info.sender,
-        wrapper.amount,
-        api.addr_validate(&wrapper.sender)?,
+        queued.msg,
+        queued.token,
+        queued.amount,
+        queued.sender,
     )
 }
 
+pub fn execute_cancel_transfer(
+    deps: DepsMut,
+    info: MessageInfo,
+    id: u64,
+) -> Result<Response, ContractError> {
+    // a transfer whose release and refund failed is taken back the same way
+    let queued = match QUEUED_TRANSFERS.may_load(deps.storage, id)? {
+        Some(queued) => Some(queued),
+        None => FAILED_RELEASES.may_load(deps.storage, id)?,
+    }
+    .filter(|queued| queued.sender == info.sender)
+    .ok_or(ContractError::NoSuchQueuedTransfer { id })?;
+    QUEUED_TRANSFERS.remove(deps.storage, id);
+    FAILED_RELEASES.remove(deps.storage, id);
+
+    let code_hash = CODE_HASH.load(deps.storage, queued.token.clone())?;
+    let config = CONFIG.load(deps.storage)?;
+    Ok(Response::new()
+        .add_message(transfer_amount(
+            queued.token.into_string(),
+            code_hash,
+            queued.sender.into_string(),
+            queued.amount,
+            config.block_size,
+        ))
+        .add_attribute("action", "cancel_transfer")
+        .add_attribute("queue_id", id.to_string()))
+}
+
+pub fn execute_set_batching(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    epoch: Option<u64>,
+) -> Result<Response, ContractError> {
+    let mut config = CONFIG.load(deps.storage)?;
+    assert_admin(&config, &info.sender)?;
+    if epoch == Some(0) {
+        return Err(ContractError::InvalidEpoch {});
+    }
+
+    config.batch_epoch = epoch;
+    CONFIG.save(deps.storage, &config)?;
+    match epoch {
+        Some(epoch) => EPOCH_END.save(deps.storage, &next_epoch_end(env.block.time, epoch))?,
+        None => EPOCH_END.remove(deps.storage),
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "set_batching")
+        .add_attribute("epoch", epoch.map_or("none".into(), |e| e.to_string())))
+}
+
 pub fn execute_ibc_transfer(
     deps: DepsMut,
     env: Env,
@@ -72,76 +527,2120 @@ pub fn execute_ibc_transfer(
     if amount.is_zero() {
         return Err(ContractError::NoFunds {});
     }
+    let config = CONFIG.load(deps.storage)?;
+    if config.paused.outbound {
+        return Err(ContractError::Paused {
+            direction: "outbound".into(),
+        });
+    }
+    let (channel, chain) =
+        resolve_target(deps.storage, token_address.as_str(), msg.channel, msg.chain)?;
     // ensure the requested channel is registered
-    if !CHANNEL_INFO.has(deps.storage, &msg.channel) {
-        return Err(ContractError::NoSuchChannel { id: msg.channel });
+    if !CHANNEL_INFO.has(deps.storage, &channel) {
+        return Err(ContractError::NoSuchChannel { id: channel });
+    }
+    if RETIRED_CHANNELS.has(deps.storage, &channel) {
+        return Err(ContractError::ChannelRetired { id: channel });
+    }
+    if let Some(route) = TOKEN_ROUTES.may_load(deps.storage, token_address.as_str())? {
+        if !route.permits(&channel) {
+            return Err(ContractError::ChannelNotPermitted {
+                token: token_address.into_string(),
+                channel,
+            });
+        }
+    }
+    if let Some(chain) = chain {
+        chain.validate_address(&msg.remote_address)?;
+    }
+
+    if config.escrow_guard {
+        // the sent tokens are already in the balance, but not yet in the liabilities
+        let status = reconcile(deps.as_ref(), &env, token_address.as_str())?;
+        if status.balance < status.liabilities + amount {
+            return Err(ContractError::EscrowShort {
+                token: token_address.into_string(),
+                deficit: status.liabilities + amount - status.balance,
+            });
+        }
     }
 
     // Absolute timeout is in unix epoch
     let timeout = env.block.time.plus_seconds(msg.timeout);
 
+    // the fee is taken out before the packet is built, only the rest goes over the channel
+    let fee = fee_schedule(deps.storage, &config.fees, token_address.as_str())?.fee_for(amount)?;
+    let net_amount = amount - fee;
+
+    let packet_sender = match msg.sender_mode {
+        SenderMode::Address => sender.to_string(),
+        SenderMode::Contract => env.contract.address.to_string(),
+        SenderMode::Pseudonym => pseudonym(deps.storage, &env, &sender)?,
+    };
+
     // build ics20 packet
     let packet = Ics20Packet::new(
-        amount,
+        net_amount,
         format!("cw20:{}", token_address),
-        sender.as_ref(),
+        &packet_sender,
         &msg.remote_address,
     );
     packet.validate()?;
 
+    check_and_update_address_quota(
+        deps.storage,
+        env.block.time,
+        token_address.as_str(),
+        sender.as_str(),
+        amount,
+    )?;
+    check_and_update_flow(
+        deps.storage,
+        env.block.time,
+        &channel,
+        token_address.as_str(),
+        net_amount,
+        FlowType::Out,
+    )?;
+
     // Update the balance now (optimistically) like ibctransfer modules.
     // In on_packet_failure (ack with error message or a timeout), we reduce the balance appropriately.
     // This means the channel works fine if success acks are not relayed.
-    increase_channel_balance(deps.storage, &msg.channel, token_address.as_str(), amount)?;
+    increase_channel_balance(deps.storage, &channel, token_address.as_str(), net_amount)?;
+
+    record_history(
+        deps.storage,
+        &sender,
+        HistoryAction::Transfer,
+        token_address.as_str(),
+        amount,
+        &channel,
+        &msg.remote_address,
+        env.block.time,
+    )?;
+
+    record_recent_user(deps.storage, &sender)?;
+
+    // refunds go to the real sender, which the packet may not carry
+    let data = to_binary(&packet)?;
+    hold_packet_sender(deps.storage, &channel, &packet_key(&data), &sender)?;
+    let mut res = Response::new();
+    if !fee.is_zero() {
+        hold_packet_fee(deps.storage, &channel, &packet_key(&data), fee)?;
+        if config.fees.accrue {
+            accrue_fee(deps.storage, token_address.as_str(), fee)?;
+        } else {
+            let collector =
+                config
+                    .fees
+                    .collector
+                    .clone()
+                    .ok_or(ContractError::InvalidFeeConfig {
+                        reason: "no fee collector".into(),
+                    })?;
+            let code_hash = CODE_HASH.load(deps.storage, token_address.clone())?;
+            res = res.add_message(transfer_amount(
+                token_address.to_string(),
+                code_hash,
+                collector.into_string(),
+                fee,
+                config.block_size,
+            ));
+        }
+    }
 
     // send response
-    let res = Response::new()
+    let mut res = res
         .add_message(IbcMsg::SendPacket {
-            channel_id: msg.channel,
-            data: to_binary(&packet)?,
+            channel_id: channel,
+            data,
             timeout: timeout.into(),
         })
         .add_attribute("action", "transfer")
         .add_attribute("sender", &packet.sender)
         .add_attribute("receiver", &packet.receiver)
         .add_attribute("denom", &packet.denom)
-        .add_attribute("amount", &packet.amount.to_string());
+        .add_attribute("amount", &packet.amount.to_string())
+        .add_attribute("fee", fee);
+    redact_attributes(&config, &mut res.attributes);
     Ok(res)
 }
 
-fn register_tokens(deps: DepsMut, env: Env, tokens: Vec<Snip20Data>) -> StdResult<Vec<SubMsg>> {
-    let mut output_msgs = vec![];
+// an address in the bridge's own format that cannot be traced back to the user
+fn pseudonym(storage: &mut dyn Storage, env: &Env, user: &Addr) -> StdResult<String> {
+    let seed = next_seed(storage, env, user.as_bytes())?;
+    // hashed again, the seed itself must stay secret
+    let hash = Sha256::new().chain(&seed).chain(b"pseudonym").finalize();
+    bech32::encode(
+        address_prefix(env.contract.address.as_str()),
+        (&hash[..20]).to_base32(),
+        Variant::Bech32,
+    )
+    .map_err(|e| StdError::generic_err(e.to_string()))
+}
 
-    for token in tokens {
-        let token_address = token.address;
-        let token_code_hash = token.code_hash;
+fn assert_admin(config: &Config, sender: &Addr) -> Result<(), ContractError> {
+    if *sender != config.admin {
+        return Err(ContractError::Unauthorized);
+    }
+    Ok(())
+}
 
-        CODE_HASH.save(
-            deps.storage,
-            deps.api.addr_validate(&token_address)?,
-            &token_code_hash,
-        )?;
+pub fn execute_update_admin(
+    deps: DepsMut,
+    info: MessageInfo,
+    admin: String,
+) -> Result<Response, ContractError> {
+    let mut config = CONFIG.load(deps.storage)?;
+    assert_admin(&config, &info.sender)?;
 
-        output_msgs.push(SubMsg::new(snip20::register_receive_msg(
-            env.contract.code_hash.clone(),
-            None,
-            256,
-            token_code_hash.clone(),
-            token_address.clone(),
-        )?));
-        output_msgs.push(SubMsg::new(snip20::set_viewing_key_msg(
-            "SNIP20-ICS20".into(),
-            None,
-            256,
-            token_code_hash.clone(),
-            token_address.clone(),
-        )?));
+    config.admin = deps.api.addr_validate(&admin)?;
+    CONFIG.save(deps.storage, &config)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "update_admin")
+        .add_attribute("admin", config.admin))
+}
+
+pub fn execute_set_pauser(
+    deps: DepsMut,
+    info: MessageInfo,
+    pauser: Option<String>,
+) -> Result<Response, ContractError> {
+    let mut config = CONFIG.load(deps.storage)?;
+    assert_admin(&config, &info.sender)?;
+
+    config.pauser = pauser
+        .map(|pauser| deps.api.addr_validate(&pauser))
+        .transpose()?;
+    CONFIG.save(deps.storage, &config)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "set_pauser")
+        .add_attribute(
+            "pauser",
+            config.pauser.map(Addr::into_string).unwrap_or_default(),
+        ))
+}
+
+/// The pauser may only switch pauses on, never off
+pub fn execute_pause(
+    deps: DepsMut,
+    info: MessageInfo,
+    outbound: bool,
+    inbound: bool,
+    refunds: bool,
+) -> Result<Response, ContractError> {
+    let mut config = CONFIG.load(deps.storage)?;
+    if info.sender != config.admin && Some(&info.sender) != config.pauser.as_ref() {
+        return Err(ContractError::Unauthorized);
     }
 
-    Ok(output_msgs)
+    config.paused.outbound |= outbound;
+    config.paused.inbound |= inbound;
+    config.paused.refunds |= refunds;
+    CONFIG.save(deps.storage, &config)?;
+
+    Ok(pause_response("pause", &config.paused))
 }
 
-#[entry_point]
-pub fn query(_deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
-    match msg {}
+pub fn execute_unpause(
+    deps: DepsMut,
+    info: MessageInfo,
+    outbound: bool,
+    inbound: bool,
+    refunds: bool,
+) -> Result<Response, ContractError> {
+    let mut config = CONFIG.load(deps.storage)?;
+    assert_admin(&config, &info.sender)?;
+
+    config.paused.outbound &= !outbound;
+    config.paused.inbound &= !inbound;
+    config.paused.refunds &= !refunds;
+    CONFIG.save(deps.storage, &config)?;
+
+    Ok(pause_response("unpause", &config.paused))
+}
+
+fn pause_response(action: &str, paused: &PauseState) -> Response {
+    Response::new()
+        .add_attribute("action", action)
+        .add_attribute("outbound_paused", paused.outbound.to_string())
+        .add_attribute("inbound_paused", paused.inbound.to_string())
+        .add_attribute("refunds_paused", paused.refunds.to_string())
+}
+
+pub fn execute_set_rate_limit(
+    deps: DepsMut,
+    info: MessageInfo,
+    channel: String,
+    token: String,
+    quota: Quota,
+) -> Result<Response, ContractError> {
+    assert_admin(&CONFIG.load(deps.storage)?, &info.sender)?;
+    quota.validate()?;
+    let token = deps.api.addr_validate(&token)?;
+    if !CHANNEL_INFO.has(deps.storage, &channel) {
+        return Err(ContractError::NoSuchChannel { id: channel });
+    }
+
+    let key = (channel.as_str(), token.as_str());
+    let mut limits = RATE_LIMITS.may_load(deps.storage, key)?.unwrap_or_default();
+    limits.retain(|limit| limit.quota.name != quota.name);
+    let name = quota.name.clone();
+    limits.push(RateLimit::new(quota));
+    RATE_LIMITS.save(deps.storage, key, &limits)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "set_rate_limit")
+        .add_attribute("channel", channel)
+        .add_attribute("token", token)
+        .add_attribute("quota", name))
+}
+
+pub fn execute_remove_rate_limit(
+    deps: DepsMut,
+    info: MessageInfo,
+    channel: String,
+    token: String,
+    name: String,
+) -> Result<Response, ContractError> {
+    assert_admin(&CONFIG.load(deps.storage)?, &info.sender)?;
+
+    let key = (channel.as_str(), token.as_str());
+    let mut limits = RATE_LIMITS.may_load(deps.storage, key)?.unwrap_or_default();
+    let count = limits.len();
+    limits.retain(|limit| limit.quota.name != name);
+    if limits.len() == count {
+        return Err(ContractError::NoSuchQuota {
+            channel,
+            denom: token,
+            name,
+        });
+    }
+    if limits.is_empty() {
+        RATE_LIMITS.remove(deps.storage, key);
+    } else {
+        RATE_LIMITS.save(deps.storage, key, &limits)?;
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "remove_rate_limit")
+        .add_attribute("channel", channel)
+        .add_attribute("token", token)
+        .add_attribute("quota", name))
+}
+
+pub fn execute_reset_rate_limit(
+    deps: DepsMut,
+    info: MessageInfo,
+    channel: String,
+    token: String,
+    name: String,
+) -> Result<Response, ContractError> {
+    assert_admin(&CONFIG.load(deps.storage)?, &info.sender)?;
+
+    let key = (channel.as_str(), token.as_str());
+    let mut limits = RATE_LIMITS.may_load(deps.storage, key)?.unwrap_or_default();
+    let index = limits
+        .iter()
+        .position(|limit| limit.quota.name == name)
+        .ok_or_else(|| ContractError::NoSuchQuota {
+            channel: channel.clone(),
+            denom: token.clone(),
+            name: name.clone(),
+        })?;
+    let quota = limits.remove(index).quota;
+    limits.insert(index, RateLimit::new(quota));
+    RATE_LIMITS.save(deps.storage, key, &limits)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "reset_rate_limit")
+        .add_attribute("channel", channel)
+        .add_attribute("token", token)
+        .add_attribute("quota", name))
+}
+
+pub fn execute_set_address_quota(
+    deps: DepsMut,
+    info: MessageInfo,
+    token: String,
+    quota: Option<AddressQuota>,
+) -> Result<Response, ContractError> {
+    assert_admin(&CONFIG.load(deps.storage)?, &info.sender)?;
+    let token = deps.api.addr_validate(&token)?;
+
+    match quota {
+        Some(quota) => {
+            if quota.window == 0 {
+                return Err(ContractError::InvalidQuota {
+                    name: token.into_string(),
+                    reason: "window must be positive".into(),
+                });
+            }
+            ADDRESS_QUOTAS.save(deps.storage, token.as_str(), &quota)?;
+        }
+        None => ADDRESS_QUOTAS.remove(deps.storage, token.as_str()),
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "set_address_quota")
+        .add_attribute("token", token))
+}
+
+pub fn execute_set_quota_override(
+    deps: DepsMut,
+    info: MessageInfo,
+    token: String,
+    address: String,
+    max_amount: Option<Uint128>,
+) -> Result<Response, ContractError> {
+    assert_admin(&CONFIG.load(deps.storage)?, &info.sender)?;
+    let token = deps.api.addr_validate(&token)?;
+    let address = deps.api.addr_validate(&address)?;
+
+    ADDRESS_QUOTA_OVERRIDES.save(
+        deps.storage,
+        (token.as_str(), address.as_str()),
+        &QuotaOverride { max_amount },
+    )?;
+
+    Ok(Response::new()
+        .add_attribute("action", "set_quota_override")
+        .add_attribute("token", token)
+        .add_attribute("address", address))
+}
+
+pub fn execute_remove_quota_override(
+    deps: DepsMut,
+    info: MessageInfo,
+    token: String,
+    address: String,
+) -> Result<Response, ContractError> {
+    assert_admin(&CONFIG.load(deps.storage)?, &info.sender)?;
+    let token = deps.api.addr_validate(&token)?;
+    let address = deps.api.addr_validate(&address)?;
+
+    let key = (token.as_str(), address.as_str());
+    if !ADDRESS_QUOTA_OVERRIDES.has(deps.storage, key) {
+        return Err(ContractError::NoSuchQuotaOverride {
+            token: token.into_string(),
+            address: address.into_string(),
+        });
+    }
+    ADDRESS_QUOTA_OVERRIDES.remove(deps.storage, key);
+
+    Ok(Response::new()
+        .add_attribute("action", "remove_quota_override")
+        .add_attribute("token", token)
+        .add_attribute("address", address))
+}
+
+pub fn execute_set_escrow_cap(
+    deps: DepsMut,
+    info: MessageInfo,
+    token: String,
+    cap: Option<EscrowCap>,
+) -> Result<Response, ContractError> {
+    assert_admin(&CONFIG.load(deps.storage)?, &info.sender)?;
+    let token = deps.api.addr_validate(&token)?;
+
+    match cap {
+        Some(cap) => ESCROW_CAPS.save(deps.storage, token.as_str(), &cap)?,
+        None => ESCROW_CAPS.remove(deps.storage, token.as_str()),
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "set_escrow_cap")
+        .add_attribute("token", token))
+}
+
+pub fn execute_set_channel_escrow_cap(
+    deps: DepsMut,
+    info: MessageInfo,
+    channel: String,
+    token: String,
+    cap: Option<Uint128>,
+) -> Result<Response, ContractError> {
+    assert_admin(&CONFIG.load(deps.storage)?, &info.sender)?;
+    let token = deps.api.addr_validate(&token)?;
+    if !CHANNEL_INFO.has(deps.storage, &channel) {
+        return Err(ContractError::NoSuchChannel { id: channel });
+    }
+
+    let key = (channel.as_str(), token.as_str());
+    match cap {
+        Some(cap) => CHANNEL_ESCROW_CAPS.save(deps.storage, key, &cap)?,
+        None => CHANNEL_ESCROW_CAPS.remove(deps.storage, key),
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "set_channel_escrow_cap")
+        .add_attribute("channel", channel)
+        .add_attribute("token", token))
+}
+
+pub fn execute_set_fees(
+    deps: DepsMut,
+    info: MessageInfo,
+    default: FeeSchedule,
+    collector: Option<String>,
+    accrue: bool,
+    refund_fee: bool,
+) -> Result<Response, ContractError> {
+    let mut config = CONFIG.load(deps.storage)?;
+    assert_admin(&config, &info.sender)?;
+
+    let fees = FeeConfig {
+        default,
+        collector: collector
+            .map(|collector| deps.api.addr_validate(&collector))
+            .transpose()?,
+        accrue,
+        refund_fee,
+    };
+    fees.validate()?;
+    if fees.collector.is_none() && !fees.accrue {
+        return Err(ContractError::InvalidFeeConfig {
+            reason: "fees must either accrue or go to a collector".into(),
+        });
+    }
+    // an incentive set while fees accrued must be lifted before they stop accruing
+    config.relayer_incentive.validate(&fees)?;
+    config.fees = fees;
+    CONFIG.save(deps.storage, &config)?;
+
+    Ok(Response::new().add_attribute("action", "set_fees"))
+}
+
+pub fn execute_set_token_fee(
+    deps: DepsMut,
+    info: MessageInfo,
+    token: String,
+    schedule: Option<FeeSchedule>,
+) -> Result<Response, ContractError> {
+    assert_admin(&CONFIG.load(deps.storage)?, &info.sender)?;
+    let token = deps.api.addr_validate(&token)?;
+
+    match schedule {
+        Some(schedule) => {
+            schedule.validate()?;
+            TOKEN_FEES.save(deps.storage, token.as_str(), &schedule)?;
+        }
+        None => TOKEN_FEES.remove(deps.storage, token.as_str()),
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "set_token_fee")
+        .add_attribute("token", token))
+}
+
+/// Sends accrued fees to the collector, all of them unless amount is given
+pub fn execute_claim_fees(
+    deps: DepsMut,
+    info: MessageInfo,
+    token: String,
+    amount: Option<Uint128>,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    let collector = config.fees.collector.ok_or(ContractError::Unauthorized)?;
+    if info.sender != collector {
+        return Err(ContractError::Unauthorized);
+    }
+    let token = deps.api.addr_validate(&token)?;
+
+    let claimed = take_accrued_fee(deps.storage, token.as_str(), amount.unwrap_or(Uint128::MAX))?;
+    if claimed.is_zero() {
+        return Err(ContractError::NoFunds {});
+    }
+    let code_hash = CODE_HASH.load(deps.storage, token.clone())?;
+
+    Ok(Response::new()
+        .add_message(transfer_amount(
+            token.to_string(),
+            code_hash,
+            collector.into_string(),
+            claimed,
+            config.block_size,
+        ))
+        .add_attribute("action", "claim_fees")
+        .add_attribute("token", token)
+        .add_attribute("amount", claimed))
+}
+
+pub fn execute_set_relayer_incentive(
+    deps: DepsMut,
+    info: MessageInfo,
+    fee_share_bps: u16,
+    flat: Uint128,
+) -> Result<Response, ContractError> {
+    let mut config = CONFIG.load(deps.storage)?;
+    assert_admin(&config, &info.sender)?;
+
+    let incentive = RelayerIncentive {
+        fee_share_bps,
+        flat,
+    };
+    incentive.validate(&config.fees)?;
+    config.relayer_incentive = incentive;
+    CONFIG.save(deps.storage, &config)?;
+
+    Ok(Response::new().add_attribute("action", "set_relayer_incentive"))
+}
+
+pub fn execute_claim_relayer_rewards(
+    deps: DepsMut,
+    info: MessageInfo,
+    token: String,
+) -> Result<Response, ContractError> {
+    let token = deps.api.addr_validate(&token)?;
+    let key = (&info.sender, token.as_str());
+    let reward = RELAYER_REWARDS
+        .may_load(deps.storage, key)?
+        .unwrap_or_default();
+    if reward.is_zero() {
+        return Err(ContractError::NoFunds {});
+    }
+    RELAYER_REWARDS.remove(deps.storage, key);
+    let code_hash = CODE_HASH.load(deps.storage, token.clone())?;
+    let config = CONFIG.load(deps.storage)?;
+
+    Ok(Response::new()
+        .add_message(transfer_amount(
+            token.to_string(),
+            code_hash,
+            info.sender.to_string(),
+            reward,
+            config.block_size,
+        ))
+        .add_attribute("action", "claim_relayer_rewards")
+        .add_attribute("relayer", info.sender)
+        .add_attribute("token", token)
+        .add_attribute("amount", reward))
+}
+
+pub fn execute_update_channel_allow_list(
+    deps: DepsMut,
+    info: MessageInfo,
+    add_connections: Vec<String>,
+    remove_connections: Vec<String>,
+    add_counterparty_ports: Vec<String>,
+    remove_counterparty_ports: Vec<String>,
+) -> Result<Response, ContractError> {
+    assert_admin(&CONFIG.load(deps.storage)?, &info.sender)?;
+
+    for connection in &add_connections {
+        ALLOWED_CONNECTIONS.save(deps.storage, connection, &true)?;
+    }
+    for connection in &remove_connections {
+        ALLOWED_CONNECTIONS.remove(deps.storage, connection);
+    }
+    for port in &add_counterparty_ports {
+        ALLOWED_COUNTERPARTY_PORTS.save(deps.storage, port, &true)?;
+    }
+    for port in &remove_counterparty_ports {
+        ALLOWED_COUNTERPARTY_PORTS.remove(deps.storage, port);
+    }
+
+    Ok(Response::new().add_attribute("action", "update_channel_allow_list"))
+}
+
+pub fn execute_set_token_route(
+    deps: DepsMut,
+    info: MessageInfo,
+    token: String,
+    route: Option<TokenRoute>,
+) -> Result<Response, ContractError> {
+    assert_admin(&CONFIG.load(deps.storage)?, &info.sender)?;
+    let token = deps.api.addr_validate(&token)?;
+
+    match route {
+        Some(route) => {
+            route.validate(deps.storage)?;
+            TOKEN_ROUTES.save(deps.storage, token.as_str(), &route)?;
+        }
+        None => TOKEN_ROUTES.remove(deps.storage, token.as_str()),
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "set_token_route")
+        .add_attribute("token", token))
+}
+
+pub fn execute_set_chain(
+    deps: DepsMut,
+    info: MessageInfo,
+    chain: String,
+    chain_info: Option<ChainInfo>,
+) -> Result<Response, ContractError> {
+    assert_admin(&CONFIG.load(deps.storage)?, &info.sender)?;
+
+    match chain_info {
+        Some(chain_info) => {
+            chain_info.validate(deps.storage)?;
+            CHAIN_REGISTRY.save(deps.storage, &chain, &chain_info)?;
+            CHANNEL_CHAINS.save(deps.storage, &chain_info.channel, &chain)?;
+        }
+        None => CHAIN_REGISTRY.remove(deps.storage, &chain),
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "set_chain")
+        .add_attribute("chain", chain))
+}
+
+fn validate_migration(
+    storage: &dyn Storage,
+    retired: &str,
+    successor: &str,
+) -> Result<(), ContractError> {
+    if retired == successor {
+        return Err(ContractError::InvalidMigration {
+            reason: "a channel cannot succeed itself".into(),
+        });
+    }
+    for channel in [retired, successor] {
+        if !CHANNEL_INFO.has(storage, channel) {
+            return Err(ContractError::NoSuchChannel { id: channel.into() });
+        }
+        if RETIRED_CHANNELS.has(storage, channel) {
+            return Err(ContractError::ChannelRetired { id: channel.into() });
+        }
+    }
+    // the successor is usually on a new connection, as the old one's client expired, so the
+    // registry has to vouch that both reach the same counterparty chain
+    let retired_chain = registered_chain_id(storage, retired)?;
+    let successor_chain = registered_chain_id(storage, successor)?;
+    match (retired_chain, successor_chain) {
+        (Some(retired_chain), Some(successor_chain)) if retired_chain == successor_chain => Ok(()),
+        (retired_chain, successor_chain) => Err(ContractError::InvalidMigration {
+            reason: format!(
+                "successor reaches chain {}, not {}",
+                successor_chain.as_deref().unwrap_or("unknown"),
+                retired_chain.as_deref().unwrap_or("unknown")
+            ),
+        }),
+    }
+}
+
+fn migrate_channel(
+    deps: DepsMut,
+    env: Env,
+    retired: &str,
+    successor: &str,
+) -> Result<Response, ContractError> {
+    let record = migrate_channel_balances(deps.storage, env.block.time, retired, successor)?;
+    let mut res = Response::new()
+        .add_attribute("action", "migrate_channel")
+        .add_attribute("channel", retired)
+        .add_attribute("successor", successor);
+    // events are public, so they show what anyone could query
+    for moved in reveal_moved(deps.storage, record.balances, None)? {
+        res = res.add_attribute(format!("moved_{}", moved.token), moved.amount);
+    }
+    Ok(res)
+}
+
+pub fn execute_migrate_channel(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    channel: String,
+    successor: String,
+    delay: Option<u64>,
+) -> Result<Response, ContractError> {
+    assert_admin(&CONFIG.load(deps.storage)?, &info.sender)?;
+    validate_migration(deps.storage, &channel, &successor)?;
+
+    match delay {
+        Some(delay) if delay > 0 => {
+            let pending = PendingMigration {
+                retired: channel.clone(),
+                successor,
+                not_before: env.block.time.plus_seconds(delay),
+            };
+            PENDING_MIGRATIONS.save(deps.storage, &channel, &pending)?;
+            Ok(Response::new()
+                .add_attribute("action", "schedule_channel_migration")
+                .add_attribute("channel", channel)
+                .add_attribute("successor", pending.successor)
+                .add_attribute("not_before", pending.not_before.to_string()))
+        }
+        _ => {
+            PENDING_MIGRATIONS.remove(deps.storage, &channel);
+            migrate_channel(deps, env, &channel, &successor)
+        }
+    }
+}
+
+pub fn execute_complete_channel_migration(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    channel: String,
+) -> Result<Response, ContractError> {
+    assert_admin(&CONFIG.load(deps.storage)?, &info.sender)?;
+    let pending = PENDING_MIGRATIONS
+        .may_load(deps.storage, &channel)?
+        .ok_or_else(|| ContractError::InvalidMigration {
+            reason: format!("no migration scheduled for {}", channel),
+        })?;
+    if env.block.time < pending.not_before {
+        return Err(ContractError::MigrationLocked {
+            not_before: pending.not_before,
+        });
+    }
+    // either channel may have been retired while the migration waited
+    validate_migration(deps.storage, &channel, &pending.successor)?;
+
+    PENDING_MIGRATIONS.remove(deps.storage, &channel);
+    migrate_channel(deps, env, &channel, &pending.successor)
+}
+
+pub fn execute_cancel_channel_migration(
+    deps: DepsMut,
+    info: MessageInfo,
+    channel: String,
+) -> Result<Response, ContractError> {
+    assert_admin(&CONFIG.load(deps.storage)?, &info.sender)?;
+    PENDING_MIGRATIONS.remove(deps.storage, &channel);
+
+    Ok(Response::new()
+        .add_attribute("action", "cancel_channel_migration")
+        .add_attribute("channel", channel))
+}
+
+pub fn execute_set_escrow_guard(
+    deps: DepsMut,
+    info: MessageInfo,
+    enabled: bool,
+) -> Result<Response, ContractError> {
+    let mut config = CONFIG.load(deps.storage)?;
+    assert_admin(&config, &info.sender)?;
+
+    config.escrow_guard = enabled;
+    CONFIG.save(deps.storage, &config)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "set_escrow_guard")
+        .add_attribute("enabled", enabled.to_string()))
+}
+
+pub fn execute_sweep(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    token: String,
+    recipient: String,
+    amount: Uint128,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    assert_admin(&config, &info.sender)?;
+    if amount.is_zero() {
+        return Err(ContractError::NoFunds {});
+    }
+    let token = deps.api.addr_validate(&token)?;
+    let recipient = deps.api.addr_validate(&recipient)?;
+
+    // escrow, fees and relayer rewards stay untouched
+    let status = reconcile(deps.as_ref(), &env, token.as_str())?;
+    if amount > status.surplus {
+        return Err(ContractError::SurplusExceeded {
+            token: token.into_string(),
+            surplus: status.surplus,
+        });
+    }
+
+    let code_hash = CODE_HASH.load(deps.storage, token.clone())?;
+    let event = Event::new("sweep")
+        .add_attribute("token", &token)
+        .add_attribute("recipient", &recipient)
+        .add_attribute("amount", amount)
+        .add_attribute("surplus", status.surplus)
+        .add_attribute("admin", info.sender);
+    Ok(Response::new()
+        .add_message(transfer_amount(
+            token.into_string(),
+            code_hash,
+            recipient.into_string(),
+            amount,
+            config.block_size,
+        ))
+        .add_event(event)
+        .add_attribute("action", "sweep"))
+}
+
+pub fn execute_rotate_viewing_keys(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    entropy: Option<String>,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    assert_admin(&config, &info.sender)?;
+
+    let entropy = [
+        entropy.unwrap_or_default().as_bytes(),
+        info.sender.as_bytes(),
+    ]
+    .concat();
+    let key = new_bridge_viewing_key(deps.storage, &env, &entropy)?;
+
+    let tokens: Vec<(Addr, String)> = CODE_HASH
+        .range(deps.storage, None, None, Order::Ascending)
+        .collect::<StdResult<_>>()?;
+    let mut msgs = vec![];
+    for (token, code_hash) in tokens {
+        msgs.push(snip20::set_viewing_key_msg(
+            key.clone(),
+            None,
+            config.block_size.into(),
+            code_hash,
+            token.into_string(),
+        )?);
+    }
+
+    Ok(Response::new()
+        .add_messages(msgs)
+        .add_attribute("action", "rotate_viewing_keys"))
+}
+
+pub fn execute_set_private_events(
+    deps: DepsMut,
+    info: MessageInfo,
+    enabled: bool,
+) -> Result<Response, ContractError> {
+    let mut config = CONFIG.load(deps.storage)?;
+    assert_admin(&config, &info.sender)?;
+
+    config.private_events = enabled;
+    CONFIG.save(deps.storage, &config)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "set_private_events")
+        .add_attribute("enabled", enabled.to_string()))
+}
+
+pub fn execute_set_block_size(
+    deps: DepsMut,
+    info: MessageInfo,
+    block_size: u16,
+) -> Result<Response, ContractError> {
+    let mut config = CONFIG.load(deps.storage)?;
+    assert_admin(&config, &info.sender)?;
+    if block_size == 0 {
+        return Err(ContractError::InvalidBlockSize {});
+    }
+
+    config.block_size = block_size;
+    CONFIG.save(deps.storage, &config)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "set_block_size")
+        .add_attribute("block_size", block_size.to_string()))
+}
+
+pub fn execute_set_token_decoys(
+    deps: DepsMut,
+    info: MessageInfo,
+    token: String,
+    count: Option<u8>,
+) -> Result<Response, ContractError> {
+    assert_admin(&CONFIG.load(deps.storage)?, &info.sender)?;
+    let token = deps.api.addr_validate(&token)?;
+
+    match count {
+        Some(count) => {
+            validate_decoy_count(count)?;
+            TOKEN_DECOYS.save(deps.storage, token.as_str(), &count)?;
+        }
+        None => TOKEN_DECOYS.remove(deps.storage, token.as_str()),
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "set_token_decoys")
+        .add_attribute("token", token))
+}
+
+pub fn execute_set_decoy_pool(
+    deps: DepsMut,
+    info: MessageInfo,
+    addresses: Vec<String>,
+) -> Result<Response, ContractError> {
+    assert_admin(&CONFIG.load(deps.storage)?, &info.sender)?;
+    let pool = addresses
+        .iter()
+        .map(|address| deps.api.addr_validate(address))
+        .collect::<StdResult<Vec<_>>>()?;
+    DECOY_POOL.save(deps.storage, &pool)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "set_decoy_pool")
+        .add_attribute("size", pool.len().to_string()))
+}
+
+pub fn execute_set_stats_visibility(
+    deps: DepsMut,
+    info: MessageInfo,
+    token: String,
+    visibility: Option<StatsVisibility>,
+) -> Result<Response, ContractError> {
+    assert_admin(&CONFIG.load(deps.storage)?, &info.sender)?;
+    let token = deps.api.addr_validate(&token)?;
+
+    match visibility {
+        Some(visibility) => {
+            let visibility = StatsVisibility {
+                issuer: visibility
+                    .issuer
+                    .map(|issuer| deps.api.addr_validate(issuer.as_str()))
+                    .transpose()?,
+                auditors: visibility
+                    .auditors
+                    .iter()
+                    .map(|auditor| deps.api.addr_validate(auditor.as_str()))
+                    .collect::<StdResult<_>>()?,
+                public_view: visibility.public_view,
+            };
+            PRIVATE_STATS.save(deps.storage, token.as_str(), &visibility)?;
+        }
+        None => PRIVATE_STATS.remove(deps.storage, token.as_str()),
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "set_stats_visibility")
+        .add_attribute("token", token))
+}
+
+pub fn execute_set_viewing_key(
+    deps: DepsMut,
+    info: MessageInfo,
+    key: String,
+) -> Result<Response, ContractError> {
+    set_user_viewing_key(deps.storage, &info.sender, &key);
+    Ok(Response::new().add_attribute("action", "set_viewing_key"))
+}
+
+pub fn execute_create_viewing_key(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    entropy: String,
+) -> Result<Response, ContractError> {
+    let key = new_user_viewing_key(deps.storage, &env, &info, entropy.as_bytes())?;
+    Ok(Response::new()
+        .set_data(to_binary(&ViewingKeyResponse { key })?)
+        .add_attribute("action", "create_viewing_key"))
+}
+
+pub fn execute_revoke_permit(
+    deps: DepsMut,
+    info: MessageInfo,
+    permit_name: String,
+) -> Result<Response, ContractError> {
+    revoke_permit(deps.storage, &info.sender, &permit_name);
+    Ok(Response::new().add_attribute("action", "revoke_permit"))
+}
+
+pub fn execute_update_token_code_hash(
+    deps: DepsMut,
+    info: MessageInfo,
+    token: String,
+    code_hash: String,
+) -> Result<Response, ContractError> {
+    assert_admin(&CONFIG.load(deps.storage)?, &info.sender)?;
+    let address = deps.api.addr_validate(&token)?;
+    if !CODE_HASH.has(deps.storage, address.clone()) {
+        return Err(ContractError::UnregisteredAsset { address });
+    }
+
+    let mut details = query_token_details(deps.as_ref(), &code_hash, &address).map_err(|err| {
+        ContractError::InvalidToken {
+            token,
+            reason: err.to_string(),
+        }
+    })?;
+    // the token's state, and with it the bridge's receive registration, survives a migration
+    details.status = match TOKEN_DETAILS.may_load(deps.storage, &address)? {
+        Some(old) if old.status == TokenStatus::Pending => TokenStatus::Pending,
+        _ => TokenStatus::Active,
+    };
+    CODE_HASH.save(deps.storage, address.clone(), &code_hash)?;
+    TOKEN_DETAILS.save(deps.storage, &address, &details)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "update_token_code_hash")
+        .add_attribute("token", address)
+        .add_attribute("code_hash", code_hash))
+}
+
+fn register_tokens(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    tokens: Vec<Snip20Data>,
+) -> Result<Vec<SubMsg>, ContractError> {
+    // registering again resets the token's status, which would skip UpdateTokenCodeHash
+    assert_admin(&CONFIG.load(deps.storage)?, &info.sender)?;
+    let mut output_msgs = vec![];
+    let viewing_key = bridge_viewing_key(deps.storage)?;
+    let block_size = CONFIG.load(deps.storage)?.block_size.into();
+
+    for (index, token) in tokens.into_iter().enumerate() {
+        let token_address = token.address;
+        let token_code_hash = token.code_hash;
+        let token = deps.api.addr_validate(&token_address)?;
+
+        // a wrong code hash or a contract that is no SNIP20 fails the query
+        let details =
+            query_token_details(deps.as_ref(), &token_code_hash, &token).map_err(|err| {
+                ContractError::InvalidToken {
+                    token: token_address.clone(),
+                    reason: err.to_string(),
+                }
+            })?;
+        CODE_HASH.save(deps.storage, token.clone(), &token_code_hash)?;
+        TOKEN_DETAILS.save(deps.storage, &token, &details)?;
+
+        let reply_id = REGISTER_ID_OFFSET + index as u64;
+        PENDING_REGISTRATIONS.save(deps.storage, reply_id, &token)?;
+        output_msgs.push(SubMsg::reply_always(
+            snip20::register_receive_msg(
+                env.contract.code_hash.clone(),
+                None,
+                block_size,
+                token_code_hash.clone(),
+                token_address.clone(),
+            )?,
+            reply_id,
+        ));
+        let reply_id = VIEWING_KEY_ID_OFFSET + index as u64;
+        PENDING_VIEWING_KEYS.save(deps.storage, reply_id, &token)?;
+        output_msgs.push(SubMsg::reply_always(
+            snip20::set_viewing_key_msg(
+                viewing_key.clone(),
+                None,
+                block_size,
+                token_code_hash.clone(),
+                token_address.clone(),
+            )?,
+            reply_id,
+        ));
+    }
+
+    Ok(output_msgs)
+}
+
+#[entry_point]
+pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
+    let block_size = CONFIG.load(deps.storage)?.block_size;
+    pad_query_result(dispatch_query(deps, env, msg), block_size.into())
+}
+
+fn dispatch_query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
+    match msg {
+        QueryMsg::Config {} => to_binary(&CONFIG.load(deps.storage)?),
+        QueryMsg::RateLimits {
+            channel,
+            token,
+            viewer,
+        } => {
+            let viewer = authenticate_viewer(deps, viewer)?;
+            to_binary(&query_rate_limits(deps, channel, token, viewer)?)
+        }
+        QueryMsg::RemainingQuota {
+            token,
+            address,
+            key,
+        } => {
+            let address = deps.api.addr_validate(&address)?;
+            check_user_viewing_key(deps.storage, &address, &key)?;
+            to_binary(&query_remaining_quota(deps, env, token, address)?)
+        }
+        QueryMsg::EscrowHeadroom { token, viewer } => {
+            let viewer = authenticate_viewer(deps, viewer)?;
+            to_binary(&query_escrow_headroom(deps, token, viewer)?)
+        }
+        QueryMsg::Fees { token } => {
+            let config = CONFIG.load(deps.storage)?;
+            to_binary(&fee_schedule(deps.storage, &config.fees, &token)?)
+        }
+        QueryMsg::RelayerStats { relayer } => to_binary(&query_relayer_stats(deps, relayer)?),
+        QueryMsg::ChannelAllowList {} => to_binary(&ChannelAllowListResponse {
+            connections: ALLOWED_CONNECTIONS
+                .keys(deps.storage, None, None, Order::Ascending)
+                .collect::<StdResult<_>>()?,
+            counterparty_ports: ALLOWED_COUNTERPARTY_PORTS
+                .keys(deps.storage, None, None, Order::Ascending)
+                .collect::<StdResult<_>>()?,
+        }),
+        QueryMsg::TokenRoute { token } => to_binary(&TOKEN_ROUTES.may_load(deps.storage, &token)?),
+        QueryMsg::Chains {} => to_binary(&ChainsResponse {
+            chains: CHAIN_REGISTRY
+                .range(deps.storage, None, None, Order::Ascending)
+                .map(|entry| entry.map(|(chain, info)| RegisteredChain { chain, info }))
+                .collect::<StdResult<_>>()?,
+        }),
+        QueryMsg::ChannelMigrations { viewer } => {
+            let viewer = authenticate_viewer(deps, viewer)?;
+            to_binary(&query_channel_migrations(deps, viewer)?)
+        }
+        QueryMsg::Reconcile { token, viewer } => {
+            let viewer = authenticate_viewer(deps, viewer)?;
+            to_binary(&query_reconcile(deps, env, token, viewer)?)
+        }
+        QueryMsg::ChannelState {
+            channel,
+            token,
+            viewer,
+        } => {
+            let viewer = authenticate_viewer(deps, viewer)?;
+            to_binary(&query_channel_state(deps, channel, token, viewer)?)
+        }
+        QueryMsg::ListTokens {} => to_binary(&query_list_tokens(deps)?),
+        QueryMsg::ListChannels {} => to_binary(&ListChannelsResponse {
+            channels: vec![TRANSFER_STATUS_CHANNEL.to_string()],
+        }),
+        QueryMsg::ChannelInfo { channels, viewer } => {
+            let address = deps.api.addr_validate(&viewer.address)?;
+            check_user_viewing_key(deps.storage, &address, &viewer.key)?;
+            to_binary(&query_channel_info(deps, env, address, channels)?)
+        }
+        QueryMsg::BatchStatus {} => to_binary(&BatchStatusResponse {
+            epoch_end: EPOCH_END.may_load(deps.storage)?,
+            queued: QUEUED_TRANSFERS
+                .keys(deps.storage, None, None, Order::Ascending)
+                .count() as u64,
+        }),
+        QueryMsg::TransferHistory {
+            address,
+            key,
+            page,
+            page_size,
+        } => {
+            let address = deps.api.addr_validate(&address)?;
+            check_user_viewing_key(deps.storage, &address, &key)?;
+            to_binary(&query_transfer_history(deps, address, page, page_size)?)
+        }
+        QueryMsg::WithPermit { permit, query } => match query {
+            QueryWithPermit::TransferHistory { page, page_size } => {
+                let address = validate_permit(deps, &env, &permit, TokenPermissions::History)?;
+                to_binary(&query_transfer_history(deps, address, page, page_size)?)
+            }
+            QueryWithPermit::ChannelState { channel, token } => {
+                let address = validate_permit(deps, &env, &permit, TokenPermissions::Balance)?;
+                to_binary(&query_channel_state(deps, channel, token, Some(address))?)
+            }
+            QueryWithPermit::RateLimits { channel, token } => {
+                let address = validate_permit(deps, &env, &permit, TokenPermissions::Balance)?;
+                to_binary(&query_rate_limits(deps, channel, token, Some(address))?)
+            }
+            QueryWithPermit::EscrowHeadroom { token } => {
+                let address = validate_permit(deps, &env, &permit, TokenPermissions::Balance)?;
+                to_binary(&query_escrow_headroom(deps, token, Some(address))?)
+            }
+            QueryWithPermit::AccruedFees { token } => {
+                let address = validate_permit(deps, &env, &permit, TokenPermissions::Balance)?;
+                to_binary(&query_accrued_fees(deps, token, Some(address))?)
+            }
+            QueryWithPermit::Reconcile { token } => {
+                let address = validate_permit(deps, &env, &permit, TokenPermissions::Balance)?;
+                to_binary(&query_reconcile(deps, env, token, Some(address))?)
+            }
+            QueryWithPermit::RemainingQuota { token } => {
+                let address = validate_permit(deps, &env, &permit, TokenPermissions::History)?;
+                to_binary(&query_remaining_quota(deps, env, token, address)?)
+            }
+            QueryWithPermit::ChannelInfo { channels } => {
+                let address = validate_permit(deps, &env, &permit, TokenPermissions::Owner)?;
+                to_binary(&query_channel_info(deps, env, address, channels)?)
+            }
+        },
+        QueryMsg::AccruedFees { token, viewer } => {
+            let viewer = authenticate_viewer(deps, viewer)?;
+            to_binary(&query_accrued_fees(deps, token, viewer)?)
+        }
+    }
+}
+
+// only call once the querier is authenticated as address
+fn query_transfer_history(
+    deps: Deps,
+    address: Addr,
+    page: Option<u32>,
+    page_size: u32,
+) -> StdResult<TransferHistoryResponse> {
+    let (entries, total) =
+        history_page(deps.storage, &address, page.unwrap_or_default(), page_size)?;
+    Ok(TransferHistoryResponse { entries, total })
+}
+
+// the viewer's address once their viewing key checks out
+fn authenticate_viewer(deps: Deps, viewer: Option<ViewerInfo>) -> StdResult<Option<Addr>> {
+    viewer
+        .map(|viewer| {
+            let address = deps.api.addr_validate(&viewer.address)?;
+            check_user_viewing_key(deps.storage, &address, &viewer.key)?;
+            Ok(address)
+        })
+        .transpose()
+}
+
+fn query_rate_limits(
+    deps: Deps,
+    channel: String,
+    token: String,
+    viewer: Option<Addr>,
+) -> StdResult<Vec<RateLimit>> {
+    let access = stats_access(deps.storage, &token, viewer.as_ref())?;
+    RATE_LIMITS
+        .may_load(deps.storage, (&channel, &token))?
+        .unwrap_or_default()
+        .into_iter()
+        .map(|limit| reveal_rate_limit(access, limit))
+        .collect()
+}
+
+fn query_accrued_fees(deps: Deps, token: String, viewer: Option<Addr>) -> StdResult<Uint128> {
+    let access = stats_access(deps.storage, &token, viewer.as_ref())?;
+    reveal(
+        access,
+        ACCRUED_FEES
+            .may_load(deps.storage, &token)?
+            .unwrap_or_default(),
+    )
+}
+
+fn query_reconcile(
+    deps: Deps,
+    env: Env,
+    token: String,
+    viewer: Option<Addr>,
+) -> StdResult<ReconcileResponse> {
+    let access = stats_access(deps.storage, &token, viewer.as_ref())?;
+    reveal_reconcile(access, reconcile(deps, &env, &token)?)
+}
+
+fn query_channel_migrations(
+    deps: Deps,
+    viewer: Option<Addr>,
+) -> StdResult<ChannelMigrationsResponse> {
+    let pending = PENDING_MIGRATIONS
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|entry| entry.map(|(_, pending)| pending))
+        .collect::<StdResult<_>>()?;
+    let executed = MIGRATION_LOG
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|entry| {
+            let (_, mut record) = entry?;
+            record.balances = reveal_moved(deps.storage, record.balances, viewer.as_ref())?;
+            Ok(record)
+        })
+        .collect::<StdResult<_>>()?;
+    Ok(ChannelMigrationsResponse { pending, executed })
+}
+
+// moved balances as the viewer may see them, leaving out tokens whose statistics are hidden
+fn reveal_moved(
+    storage: &dyn Storage,
+    balances: Vec<MovedBalance>,
+    viewer: Option<&Addr>,
+) -> StdResult<Vec<MovedBalance>> {
+    let mut revealed = vec![];
+    for moved in balances {
+        let access = stats_access(storage, &moved.token, viewer)?;
+        if access != StatsAccess::Denied {
+            revealed.push(MovedBalance {
+                amount: reveal(access, moved.amount)?,
+                token: moved.token,
+            });
+        }
+    }
+    Ok(revealed)
+}
+
+fn query_channel_state(
+    deps: Deps,
+    channel: String,
+    token: String,
+    viewer: Option<Addr>,
+) -> StdResult<ChannelStateResponse> {
+    let access = stats_access(deps.storage, &token, viewer.as_ref())?;
+    let state = CHANNEL_STATE
+        .may_load(deps.storage, (&channel, &token))?
+        .unwrap_or_default();
+    Ok(ChannelStateResponse {
+        outstanding: reveal(access, state.outstanding)?,
+        total_sent: reveal(access, state.total_sent)?,
+        exact: access == StatsAccess::Exact,
+    })
+}
+
+fn query_list_tokens(deps: Deps) -> StdResult<ListTokensResponse> {
+    let tokens = CODE_HASH
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|entry| {
+            let (address, code_hash) = entry?;
+            let details = TOKEN_DETAILS.may_load(deps.storage, &address)?;
+            Ok(RegisteredToken {
+                address,
+                code_hash,
+                details,
+            })
+        })
+        .collect::<StdResult<_>>()?;
+    Ok(ListTokensResponse { tokens })
+}
+
+// only call once the querier is authenticated as address
+fn query_channel_info(
+    deps: Deps,
+    env: Env,
+    address: Addr,
+    channels: Vec<String>,
+) -> StdResult<ChannelInfoResponse> {
+    let seed = notification_seed(deps.storage, deps.api, &address)?;
+    let channels = channels
+        .iter()
+        .map(|channel| channel_info(deps.storage, &address, channel, &seed))
+        .collect::<StdResult<_>>()?;
+    Ok(ChannelInfoResponse {
+        as_of_block: env.block.height.into(),
+        seed: Binary::from(seed),
+        channels,
+    })
+}
+
+fn query_relayer_stats(deps: Deps, relayer: String) -> StdResult<RelayerStatsResponse> {
+    let relayer = deps.api.addr_validate(&relayer)?;
+    let stats = RELAYER_STATS
+        .may_load(deps.storage, &relayer)?
+        .unwrap_or_default();
+    let rewards = RELAYER_REWARDS
+        .prefix(&relayer)
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| {
+            let (token, amount) = item?;
+            Ok(RelayerReward { token, amount })
+        })
+        .collect::<StdResult<_>>()?;
+    Ok(RelayerStatsResponse { stats, rewards })
+}
+
+fn query_escrow_headroom(
+    deps: Deps,
+    token: String,
+    viewer: Option<Addr>,
+) -> StdResult<EscrowHeadroomResponse> {
+    let access = stats_access(deps.storage, &token, viewer.as_ref())?;
+    let channels = CHANNEL_INFO
+        .keys(deps.storage, None, None, Order::Ascending)
+        .map(|channel| {
+            let channel = channel?;
+            let outstanding = CHANNEL_STATE
+                .may_load(deps.storage, (&channel, &token))?
+                .unwrap_or_default()
+                .outstanding;
+            let cap = channel_escrow_cap(deps.storage, &channel, &token)?;
+            Ok(ChannelHeadroom {
+                channel,
+                outstanding: reveal(access, outstanding)?,
+                cap,
+                headroom: cap
+                    .map(|cap| reveal(access, cap.saturating_sub(outstanding)))
+                    .transpose()?,
+            })
+        })
+        .collect::<StdResult<Vec<_>>>()?;
+
+    let outstanding = total_outstanding(deps.storage, &token)?;
+    let cap = ESCROW_CAPS
+        .may_load(deps.storage, &token)?
+        .and_then(|cap| cap.total);
+    Ok(EscrowHeadroomResponse {
+        outstanding: reveal(access, outstanding)?,
+        cap,
+        headroom: cap
+            .map(|cap| reveal(access, cap.saturating_sub(outstanding)))
+            .transpose()?,
+        channels,
+    })
+}
+
+// only call once the querier is authenticated as address
+fn query_remaining_quota(
+    deps: Deps,
+    env: Env,
+    token: String,
+    address: Addr,
+) -> StdResult<RemainingQuotaResponse> {
+    let status = address_quota_status(deps.storage, env.block.time, &token, address.as_str())?;
+    Ok(match status {
+        Some((max_amount, used, next_release)) => RemainingQuotaResponse {
+            max_amount: Some(max_amount),
+            used,
+            remaining: Some(max_amount.saturating_sub(used)),
+            next_release,
+        },
+        None => RemainingQuotaResponse {
+            max_amount: None,
+            used: Uint128::zero(),
+            remaining: None,
+            next_release: None,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
+    use cosmwasm_std::ReplyOn;
+
+    use crate::state::{CanonicalChannel, PublicView, StatsVisibility};
+    use crate::test_helpers::{
+        add_channel, add_queued_transfer, add_token, mock_token, receive_msg, remote_address,
+        setup, snip20_transfer, transfer_msg, ADMIN,
+    };
+
+    fn pause(outbound: bool, inbound: bool, refunds: bool) -> ExecuteMsg {
+        ExecuteMsg::Pause {
+            outbound,
+            inbound,
+            refunds,
+            padding: None,
+        }
+    }
+
+    #[test]
+    fn the_pauser_pauses_and_only_the_admin_unpauses() {
+        let mut deps = mock_dependencies();
+        setup(deps.as_mut());
+        let set_pauser = ExecuteMsg::SetPauser {
+            pauser: Some("pauser".into()),
+            padding: None,
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), set_pauser).unwrap();
+
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("stranger", &[]),
+            pause(true, false, false),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized);
+
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("pauser", &[]),
+            pause(true, false, true),
+        )
+        .unwrap();
+        let unpause = ExecuteMsg::Unpause {
+            outbound: true,
+            inbound: false,
+            refunds: false,
+            padding: None,
+        };
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("pauser", &[]),
+            unpause.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized);
+
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), unpause).unwrap();
+        let paused = CONFIG.load(&deps.storage).unwrap().paused;
+        assert_eq!(
+            paused,
+            PauseState {
+                outbound: false,
+                inbound: false,
+                refunds: true,
+            }
+        );
+    }
+
+    #[test]
+    fn paused_outbound_rejects_transfers() {
+        let mut deps = mock_dependencies();
+        setup(deps.as_mut());
+        add_channel(&mut deps.storage, "channel-0", "connection-0");
+        add_token(&mut deps.storage, "token");
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADMIN, &[]),
+            pause(true, false, false),
+        )
+        .unwrap();
+
+        let msg = receive_msg("alice", 100, &transfer_msg("channel-0", "remote"));
+        let err = execute(deps.as_mut(), mock_env(), mock_info("token", &[]), msg).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::Paused {
+                direction: "outbound".into()
+            }
+        );
+    }
+
+    #[test]
+    fn escrow_caps_bound_each_channel_and_the_bridge() {
+        let mut deps = mock_dependencies();
+        setup(deps.as_mut());
+        add_channel(&mut deps.storage, "channel-0", "connection-0");
+        add_channel(&mut deps.storage, "channel-1", "connection-0");
+        add_token(&mut deps.storage, "token");
+        let caps = ExecuteMsg::SetEscrowCap {
+            token: "token".into(),
+            cap: Some(EscrowCap {
+                total: Some(Uint128::new(250)),
+                per_channel: Some(Uint128::new(100)),
+            }),
+            padding: None,
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), caps).unwrap();
+        let channel_cap = ExecuteMsg::SetChannelEscrowCap {
+            channel: "channel-1".into(),
+            token: "token".into(),
+            cap: Some(Uint128::new(200)),
+            padding: None,
+        };
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADMIN, &[]),
+            channel_cap,
+        )
+        .unwrap();
+
+        let mut send = |channel: &str, amount: u128| {
+            let msg = receive_msg("alice", amount, &transfer_msg(channel, "remote"));
+            execute(deps.as_mut(), mock_env(), mock_info("token", &[]), msg)
+        };
+        send("channel-0", 100).unwrap();
+        // channel-1 has its own cap, but the bridge as a whole stops at 250
+        send("channel-1", 150).unwrap();
+        let err = send("channel-1", 1).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::EscrowCapExceeded {
+                token: "token".into(),
+                scope: "bridge".into(),
+                cap: Uint128::new(250),
+            }
+        );
+        let err = send("channel-0", 1).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::EscrowCapExceeded {
+                token: "token".into(),
+                scope: "channel channel-0".into(),
+                cap: Uint128::new(100),
+            }
+        );
+    }
+
+    fn register_chain(deps: DepsMut, chain: &str, channel: &str, prefix: &str) {
+        let msg = ExecuteMsg::SetChain {
+            chain: chain.into(),
+            info: Some(ChainInfo {
+                channel: channel.into(),
+                bech32_prefix: prefix.into(),
+                address_lengths: vec![20],
+            }),
+            padding: None,
+        };
+        execute(deps, mock_env(), mock_info(ADMIN, &[]), msg).unwrap();
+    }
+
+    fn outstanding(deps: Deps, channel: &str) -> Uint128 {
+        CHANNEL_STATE
+            .may_load(deps.storage, (channel, "token"))
+            .unwrap()
+            .unwrap_or_default()
+            .outstanding
+    }
+
+    #[test]
+    fn routes_pick_the_canonical_channel_and_refuse_others() {
+        let mut deps = mock_dependencies();
+        setup(deps.as_mut());
+        for channel in ["channel-0", "channel-1", "channel-2"] {
+            add_channel(&mut deps.storage, channel, "connection-0");
+        }
+        add_token(&mut deps.storage, "token");
+        register_chain(deps.as_mut(), "osmosis-1", "channel-0", "osmo");
+
+        let set_route = |route: TokenRoute| ExecuteMsg::SetTokenRoute {
+            token: "token".into(),
+            route: Some(route),
+            padding: None,
+        };
+        let canonical = CanonicalChannel {
+            chain: "osmosis-1".into(),
+            channel: "channel-1".into(),
+        };
+        let twice = TokenRoute {
+            allowed_channels: vec![],
+            canonical_channels: vec![canonical.clone(), canonical.clone()],
+        };
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADMIN, &[]),
+            set_route(twice),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::InvalidTokenRoute { .. }));
+        let route = TokenRoute {
+            allowed_channels: vec!["channel-0".into()],
+            canonical_channels: vec![canonical],
+        };
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADMIN, &[]),
+            set_route(route),
+        )
+        .unwrap();
+
+        let remote = remote_address("osmo");
+        let mut send = |channel: Option<&str>, chain: Option<&str>| {
+            let mut msg = transfer_msg("", &remote);
+            msg.channel = channel.map(String::from);
+            msg.chain = chain.map(String::from);
+            let msg = receive_msg("alice", 100, &msg);
+            execute(deps.as_mut(), mock_env(), mock_info("token", &[]), msg)
+        };
+        // the chain alone goes over the token's canonical channel, not the chain's own
+        send(None, Some("osmosis-1")).unwrap();
+        send(Some("channel-0"), Some("osmosis-1")).unwrap();
+        let err = send(Some("channel-2"), Some("osmosis-1")).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::ChannelNotOnChain {
+                channel: "channel-2".into(),
+                chain: "osmosis-1".into(),
+            }
+        );
+        let err = send(Some("channel-2"), None).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::ChannelNotPermitted {
+                token: "token".into(),
+                channel: "channel-2".into(),
+            }
+        );
+
+        assert_eq!(outstanding(deps.as_ref(), "channel-0"), Uint128::new(100));
+        assert_eq!(outstanding(deps.as_ref(), "channel-1"), Uint128::new(100));
+    }
+
+    #[test]
+    fn channels_migrate_to_a_successor_on_the_same_chain() {
+        let mut deps = mock_dependencies();
+        setup(deps.as_mut());
+        add_channel(&mut deps.storage, "channel-0", "connection-0");
+        // the client of connection-0 expired, channel-1 reaches the chain over a new one
+        add_channel(&mut deps.storage, "channel-1", "connection-1");
+        add_channel(&mut deps.storage, "channel-2", "connection-2");
+        add_token(&mut deps.storage, "token");
+        register_chain(deps.as_mut(), "osmosis-1", "channel-0", "osmo");
+        register_chain(deps.as_mut(), "juno-1", "channel-2", "juno");
+        increase_channel_balance(&mut deps.storage, "channel-0", "token", Uint128::new(100))
+            .unwrap();
+
+        let migrate = |successor: &str| ExecuteMsg::MigrateChannel {
+            channel: "channel-0".into(),
+            successor: successor.into(),
+            delay: None,
+            padding: None,
+        };
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADMIN, &[]),
+            migrate("channel-1"),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::InvalidMigration { .. }));
+
+        register_chain(deps.as_mut(), "osmosis-1", "channel-1", "osmo");
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADMIN, &[]),
+            migrate("channel-2"),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::InvalidMigration {
+                reason: "successor reaches chain juno-1, not osmosis-1".into()
+            }
+        );
+
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADMIN, &[]),
+            migrate("channel-1"),
+        )
+        .unwrap();
+        assert_eq!(outstanding(deps.as_ref(), "channel-0"), Uint128::zero());
+        assert_eq!(outstanding(deps.as_ref(), "channel-1"), Uint128::new(100));
+
+        let msg = receive_msg(
+            "alice",
+            100,
+            &transfer_msg("channel-0", &remote_address("osmo")),
+        );
+        let err = execute(deps.as_mut(), mock_env(), mock_info("token", &[]), msg).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::ChannelRetired {
+                id: "channel-0".into()
+            }
+        );
+    }
+
+    #[test]
+    fn migrated_amounts_follow_the_stats_visibility() {
+        let mut deps = mock_dependencies();
+        setup(deps.as_mut());
+        add_channel(&mut deps.storage, "channel-0", "connection-0");
+        add_channel(&mut deps.storage, "channel-1", "connection-0");
+        add_token(&mut deps.storage, "token");
+        add_token(&mut deps.storage, "hidden");
+        register_chain(deps.as_mut(), "osmosis-1", "channel-0", "osmo");
+        increase_channel_balance(&mut deps.storage, "channel-0", "token", Uint128::new(1234))
+            .unwrap();
+        increase_channel_balance(&mut deps.storage, "channel-0", "hidden", Uint128::new(77))
+            .unwrap();
+        for (token, public_view) in [
+            ("token", PublicView::Bucketed),
+            ("hidden", PublicView::Hidden),
+        ] {
+            let msg = ExecuteMsg::SetStatsVisibility {
+                token: token.into(),
+                visibility: Some(StatsVisibility {
+                    issuer: None,
+                    auditors: vec![],
+                    public_view,
+                }),
+                padding: None,
+            };
+            execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), msg).unwrap();
+        }
+
+        let msg = ExecuteMsg::MigrateChannel {
+            channel: "channel-0".into(),
+            successor: "channel-1".into(),
+            delay: None,
+            padding: None,
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), msg).unwrap();
+        let moved: Vec<_> = res
+            .attributes
+            .iter()
+            .filter(|attr| attr.key.starts_with("moved_"))
+            .map(|attr| (attr.key.trim_end(), attr.value.trim_end()))
+            .collect();
+        assert_eq!(moved, vec![("moved_token", "1000")]);
+
+        let migrations = |deps: Deps, viewer: Option<ViewerInfo>| {
+            let msg = QueryMsg::ChannelMigrations { viewer };
+            let res: ChannelMigrationsResponse =
+                from_binary(&query(deps, mock_env(), msg).unwrap()).unwrap();
+            res.executed[0].balances.clone()
+        };
+        assert_eq!(
+            migrations(deps.as_ref(), None),
+            vec![MovedBalance {
+                token: "token".into(),
+                amount: Uint128::new(1000),
+            }]
+        );
+
+        let msg = ExecuteMsg::SetViewingKey {
+            key: "key".into(),
+            padding: None,
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), msg).unwrap();
+        let viewer = ViewerInfo {
+            address: ADMIN.into(),
+            key: "key".into(),
+        };
+        let mut balances = migrations(deps.as_ref(), Some(viewer));
+        balances.sort_by(|a, b| a.token.cmp(&b.token));
+        assert_eq!(
+            balances,
+            vec![
+                MovedBalance {
+                    token: "hidden".into(),
+                    amount: Uint128::new(77),
+                },
+                MovedBalance {
+                    token: "token".into(),
+                    amount: Uint128::new(1234),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn only_the_admin_registers_tokens() {
+        let mut deps = mock_dependencies();
+        setup(deps.as_mut());
+        mock_token(&mut deps.querier, 0);
+        let register = ExecuteMsg::RegisterTokens {
+            tokens: vec![Snip20Data {
+                address: "token".into(),
+                code_hash: "hash".into(),
+            }],
+            padding: None,
+        };
+
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("alice", &[]),
+            register.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized);
+        assert!(!CODE_HASH.has(&deps.storage, Addr::unchecked("token")));
+
+        let res = execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), register).unwrap();
+        let ids: Vec<_> = res.messages.iter().map(|msg| msg.id).collect();
+        assert_eq!(ids, vec![REGISTER_ID_OFFSET, VIEWING_KEY_ID_OFFSET]);
+        assert_eq!(
+            TOKEN_DETAILS
+                .load(&deps.storage, &Addr::unchecked("token"))
+                .unwrap()
+                .status,
+            TokenStatus::Pending
+        );
+    }
+
+    #[test]
+    fn the_escrow_guard_refuses_transfers_the_balance_cannot_cover() {
+        let mut deps = mock_dependencies();
+        setup(deps.as_mut());
+        add_channel(&mut deps.storage, "channel-0", "connection-0");
+        add_token(&mut deps.storage, "token");
+        increase_channel_balance(&mut deps.storage, "channel-0", "token", Uint128::new(100))
+            .unwrap();
+        let guard = ExecuteMsg::SetEscrowGuard {
+            enabled: true,
+            padding: None,
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), guard).unwrap();
+
+        // 30 of the escrow went missing, the 50 sent are already in the balance
+        mock_token(&mut deps.querier, 120);
+        let msg = receive_msg("alice", 50, &transfer_msg("channel-0", "remote"));
+        let err = execute(deps.as_mut(), mock_env(), mock_info("token", &[]), msg).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::EscrowShort {
+                token: "token".into(),
+                deficit: Uint128::new(30),
+            }
+        );
+
+        mock_token(&mut deps.querier, 150);
+        let msg = receive_msg("alice", 50, &transfer_msg("channel-0", "remote"));
+        execute(deps.as_mut(), mock_env(), mock_info("token", &[]), msg).unwrap();
+    }
+
+    #[test]
+    fn only_the_surplus_is_swept() {
+        let mut deps = mock_dependencies();
+        setup(deps.as_mut());
+        add_channel(&mut deps.storage, "channel-0", "connection-0");
+        add_token(&mut deps.storage, "token");
+        increase_channel_balance(&mut deps.storage, "channel-0", "token", Uint128::new(100))
+            .unwrap();
+        mock_token(&mut deps.querier, 130);
+
+        let sweep = |amount: u128| ExecuteMsg::Sweep {
+            token: "token".into(),
+            recipient: "treasury".into(),
+            amount: Uint128::new(amount),
+            padding: None,
+        };
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("stranger", &[]),
+            sweep(10),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized);
+        let err = execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), sweep(31)).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::SurplusExceeded {
+                token: "token".into(),
+                surplus: Uint128::new(30),
+            }
+        );
+
+        let res = execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), sweep(30)).unwrap();
+        assert_eq!(
+            snip20_transfer(&res.messages[0].msg),
+            ("token".into(), "treasury".into(), Uint128::new(30))
+        );
+    }
+
+    #[test]
+    fn releases_are_bounded_by_the_limit() {
+        let mut deps = mock_dependencies();
+        for id in 0..3 {
+            add_queued_transfer(&mut deps.storage, id);
+        }
+        let env = mock_env();
+
+        let (releases, drained) = release_queue(&deps.storage, &env, 256, 2).unwrap();
+        assert_eq!(releases.len(), 2);
+        assert!(!drained);
+        assert_eq!(releases[1].id, RELEASE_ID_OFFSET + 1);
+        assert_eq!(releases[1].reply_on, ReplyOn::Error);
+
+        let (releases, drained) = release_queue(&deps.storage, &env, 256, 3).unwrap();
+        assert_eq!(releases.len(), 3);
+        assert!(drained);
+    }
+
+    #[test]
+    fn the_epoch_moves_on_once_the_queue_is_drained() {
+        let mut deps = mock_dependencies();
+        setup(deps.as_mut());
+        let mut config = CONFIG.load(&deps.storage).unwrap();
+        config.batch_epoch = Some(3600);
+        CONFIG.save(&mut deps.storage, &config).unwrap();
+        for id in 0..3 {
+            add_queued_transfer(&mut deps.storage, id);
+        }
+
+        let mut env = mock_env();
+        let end = env.block.time.plus_seconds(10);
+        EPOCH_END.save(&mut deps.storage, &end).unwrap();
+        assert_eq!(
+            execute_flush(deps.as_mut(), env.clone(), None).unwrap_err(),
+            ContractError::EpochNotOver { ends: end }
+        );
+
+        env.block.time = end;
+        let res = execute_flush(deps.as_mut(), env.clone(), Some(2)).unwrap();
+        assert_eq!(res.messages.len(), 2);
+        assert_eq!(EPOCH_END.load(&deps.storage).unwrap(), end);
+
+        // the releases only leave the queue once they run, so all are released again
+        let res = execute_flush(deps.as_mut(), env.clone(), None).unwrap();
+        assert_eq!(res.messages.len(), 3);
+        assert_eq!(
+            EPOCH_END.load(&deps.storage).unwrap(),
+            next_epoch_end(env.block.time, 3600)
+        );
+    }
+
+    #[test]
+    fn only_the_bridge_releases_queued_transfers() {
+        let mut deps = mock_dependencies();
+        setup(deps.as_mut());
+        add_queued_transfer(&mut deps.storage, 0);
+
+        let err = execute_release_transfer(deps.as_mut(), mock_env(), mock_info("alice", &[]), 0)
+            .unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized);
+        assert!(QUEUED_TRANSFERS.has(&deps.storage, 0));
+    }
+
+    #[test]
+    fn senders_cancel_transfers_whose_refund_failed() {
+        let mut deps = mock_dependencies();
+        setup(deps.as_mut());
+        add_token(&mut deps.storage, "token");
+        add_queued_transfer(&mut deps.storage, 4);
+        let queued = QUEUED_TRANSFERS.load(&deps.storage, 4).unwrap();
+        QUEUED_TRANSFERS.remove(&mut deps.storage, 4);
+        FAILED_RELEASES.save(&mut deps.storage, 4, &queued).unwrap();
+
+        let cancel = ExecuteMsg::CancelTransfer {
+            id: 4,
+            padding: None,
+        };
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("bob", &[]),
+            cancel.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::NoSuchQueuedTransfer { id: 4 });
+        let res = execute(deps.as_mut(), mock_env(), mock_info("alice", &[]), cancel).unwrap();
+        assert_eq!(
+            snip20_transfer(&res.messages[0].msg),
+            ("token".into(), "alice".into(), Uint128::new(100))
+        );
+        assert!(!FAILED_RELEASES.has(&deps.storage, 4));
+    }
+
+    #[test]
+    fn quota_overrides_are_removed_by_their_validated_address() {
+        let mut deps = mock_dependencies();
+        setup(deps.as_mut());
+        let set = ExecuteMsg::SetQuotaOverride {
+            token: "token".into(),
+            address: "alice".into(),
+            max_amount: None,
+            padding: None,
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), set).unwrap();
+
+        let remove = |address: &str| ExecuteMsg::RemoveQuotaOverride {
+            token: "token".into(),
+            address: address.into(),
+            padding: None,
+        };
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADMIN, &[]),
+            remove("Alice"),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::Std(_)));
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADMIN, &[]),
+            remove("bob"),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::NoSuchQuotaOverride {
+                token: "token".into(),
+                address: "bob".into(),
+            }
+        );
+
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADMIN, &[]),
+            remove("alice"),
+        )
+        .unwrap();
+        assert!(!ADDRESS_QUOTA_OVERRIDES.has(&deps.storage, ("token", "alice")));
+    }
 }
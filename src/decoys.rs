@@ -0,0 +1,145 @@
+use cosmwasm_std::{Addr, Env, StdResult, Storage};
+
+use crate::error::ContractError;
+use crate::state::{DECOY_POOL, RECENT_USERS, TOKEN_DECOYS};
+use crate::viewing_key::next_seed;
+
+/// How many recent bridge users are kept around as decoys
+const RECENT_USERS_KEPT: usize = 32;
+
+/// Picks are drawn from a 32 byte seed, 4 bytes each
+pub const MAX_DECOYS: u8 = 8;
+
+pub fn validate_decoy_count(count: u8) -> Result<(), ContractError> {
+    if count == 0 || count > MAX_DECOYS {
+        return Err(ContractError::InvalidDecoyConfig {
+            reason: format!("count must be between 1 and {}", MAX_DECOYS),
+        });
+    }
+    Ok(())
+}
+
+/// Remembers the address as a recent bridge user, forgetting the oldest one when full
+pub fn record_recent_user(storage: &mut dyn Storage, user: &Addr) -> StdResult<()> {
+    let mut recent = RECENT_USERS.may_load(storage)?.unwrap_or_default();
+    if recent.contains(user) {
+        return Ok(());
+    }
+    if recent.len() >= RECENT_USERS_KEPT {
+        recent.remove(0);
+    }
+    recent.push(user.clone());
+    RECENT_USERS.save(storage, &recent)
+}
+
+/// The decoys to hide the recipient of a transfer of the token among, drawn from the
+/// pool and recent bridge users. Empty if the token has no decoys set.
+pub fn pick_decoys(
+    storage: &mut dyn Storage,
+    env: &Env,
+    token: &str,
+    recipient: &str,
+) -> StdResult<Vec<Addr>> {
+    let count = match TOKEN_DECOYS.may_load(storage, token)? {
+        Some(count) => count as usize,
+        None => return Ok(vec![]),
+    };
+
+    let mut candidates = DECOY_POOL.may_load(storage)?.unwrap_or_default();
+    for user in RECENT_USERS.may_load(storage)?.unwrap_or_default() {
+        if !candidates.contains(&user) {
+            candidates.push(user);
+        }
+    }
+    candidates.retain(|candidate| candidate.as_str() != recipient);
+
+    let seed = next_seed(storage, env, recipient.as_bytes())?;
+    let mut decoys = vec![];
+    for chunk in seed.chunks_exact(4).take(count) {
+        if candidates.is_empty() {
+            break;
+        }
+        let pick = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]) as usize;
+        decoys.push(candidates.swap_remove(pick % candidates.len()));
+    }
+    Ok(decoys)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cosmwasm_std::testing::{mock_env, MockStorage};
+    use cosmwasm_std::{CosmosMsg, Uint128, WasmMsg};
+
+    use crate::ibc::transfer_amount_with_decoys;
+
+    #[test]
+    fn decoy_counts_are_bounded() {
+        assert!(validate_decoy_count(0).is_err());
+        validate_decoy_count(1).unwrap();
+        validate_decoy_count(MAX_DECOYS).unwrap();
+        assert!(validate_decoy_count(MAX_DECOYS + 1).is_err());
+    }
+
+    #[test]
+    fn only_the_latest_users_are_kept() {
+        let mut storage = MockStorage::new();
+        for i in 0..RECENT_USERS_KEPT + 2 {
+            record_recent_user(&mut storage, &Addr::unchecked(format!("user{}", i))).unwrap();
+        }
+        record_recent_user(&mut storage, &Addr::unchecked("user5")).unwrap();
+
+        let recent = RECENT_USERS.load(&storage).unwrap();
+        assert_eq!(recent.len(), RECENT_USERS_KEPT);
+        assert_eq!(recent[0], Addr::unchecked("user2"));
+    }
+
+    #[test]
+    fn decoys_are_distinct_and_never_the_recipient() {
+        let mut storage = MockStorage::new();
+        let env = mock_env();
+        assert!(pick_decoys(&mut storage, &env, "token", "alice")
+            .unwrap()
+            .is_empty());
+
+        TOKEN_DECOYS.save(&mut storage, "token", &4).unwrap();
+        let pool = vec![Addr::unchecked("pool0"), Addr::unchecked("alice")];
+        DECOY_POOL.save(&mut storage, &pool).unwrap();
+        for user in ["pool0", "bob", "carol", "dave", "erin"] {
+            record_recent_user(&mut storage, &Addr::unchecked(user)).unwrap();
+        }
+
+        let decoys = pick_decoys(&mut storage, &env, "token", "alice").unwrap();
+        assert_eq!(decoys.len(), 4);
+        assert!(!decoys.contains(&Addr::unchecked("alice")));
+        for (i, decoy) in decoys.iter().enumerate() {
+            assert!(!decoys[i + 1..].contains(decoy));
+        }
+
+        // fewer candidates than decoys wanted
+        TOKEN_DECOYS.save(&mut storage, "token", &8).unwrap();
+        let decoys = pick_decoys(&mut storage, &env, "token", "alice").unwrap();
+        assert_eq!(decoys.len(), 5);
+    }
+
+    #[test]
+    fn decoys_go_into_the_transfer() {
+        let decoys = [Addr::unchecked("bob"), Addr::unchecked("carol")];
+        let msg = transfer_amount_with_decoys(
+            "token".into(),
+            "hash".into(),
+            "alice".into(),
+            Uint128::new(5),
+            &decoys,
+            1,
+        );
+        let msg = match msg {
+            CosmosMsg::Wasm(WasmMsg::Execute { msg, .. }) => String::from_utf8(msg.0).unwrap(),
+            other => panic!("not a token message: {:?}", other),
+        };
+        assert_eq!(
+            msg,
+            r#"{"transfer":{"recipient":"alice","amount":"5","decoys":["bob","carol"]}}"#
+        );
+    }
+}
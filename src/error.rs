@@ -1,9 +1,11 @@
 use std::num::TryFromIntError;
 use std::string::FromUtf8Error;
 
-use cosmwasm_std::{Addr, StdError, Uint128};
+use cosmwasm_std::{Addr, StdError, Timestamp, Uint128};
 use thiserror::Error;
 
+use crate::state::TokenStatus;
+
 /// Never is a placeholder to ensure we don't return any errors
 #[derive(Error, Debug)]
 pub enum Never {}
@@ -28,7 +30,9 @@ pub enum ContractError {
     #[error("Amount larger than 2**64, not supported by ics20 packets")]
     AmountOverflow {},
 
-    #[error("Only supports channel with ibc version ics20-1, got {version}")]
+    #[error(
+        "Only supports channel with ibc version ics20-1, optionally fee wrapped, got {version}"
+    )]
     InvalidIbcVersion { version: String },
 
     #[error("Only supports unordered channel")]
@@ -72,6 +76,238 @@ pub enum ContractError {
 
     #[error("You can only send cw20 tokens that have been explicitly allowed by governance")]
     NotOnAllowList,
+
+    #[error("Snip20 transfer failed: {reason}")]
+    Snip20TransferFailed { reason: String },
+
+    #[error("Bridge is paused for {direction}")]
+    Paused { direction: String },
+
+    #[error("Rate limit {quota} exceeded on channel {channel} for {denom}, frees up from {reset}")]
+    RateLimitExceeded {
+        channel: String,
+        denom: String,
+        quota: String,
+        reset: Timestamp,
+    },
+
+    #[error("Invalid quota {name}: {reason}")]
+    InvalidQuota { name: String, reason: String },
+
+    #[error("No quota {name} on channel {channel} for {denom}")]
+    NoSuchQuota {
+        channel: String,
+        denom: String,
+        name: String,
+    },
+
+    #[error("Transfer exceeds the quota of {address} for {token}, remaining={remaining}")]
+    AddressQuotaExceeded {
+        address: String,
+        token: String,
+        remaining: Uint128,
+    },
+
+    #[error("Escrow cap of {cap} for {token} exceeded on {scope}")]
+    EscrowCapExceeded {
+        token: String,
+        scope: String,
+        cap: Uint128,
+    },
+
+    #[error("Transfer amount too small, must be at least {min}")]
+    AmountBelowMinimum { min: Uint128 },
+
+    #[error("Invalid fee config: {reason}")]
+    InvalidFeeConfig { reason: String },
+
+    #[error("Connection {id} is not on the allow list")]
+    ConnectionNotAllowed { id: String },
+
+    #[error("Counterparty port {port} is not on the allow list")]
+    CounterpartyPortNotAllowed { port: String },
+
+    #[error("Token {token} may not be sent over channel {channel}")]
+    ChannelNotPermitted { token: String, channel: String },
+
+    #[error("Chain {chain} is not in the registry")]
+    UnknownChain { chain: String },
+
+    #[error("Invalid remote address {address}: {reason}")]
+    InvalidRemoteAddress { address: String, reason: String },
+
+    #[error("Transfer must set a channel or a chain")]
+    NoTransferTarget {},
+
+    #[error("Invalid chain info: {reason}")]
+    InvalidChainInfo { reason: String },
+
+    #[error("Channel {id} is retired")]
+    ChannelRetired { id: String },
+
+    #[error("Invalid channel migration: {reason}")]
+    InvalidMigration { reason: String },
+
+    #[error("Channel migration is time locked until {not_before}")]
+    MigrationLocked { not_before: Timestamp },
+
+    #[error("Escrow of {token} is short by {deficit}")]
+    EscrowShort { token: String, deficit: Uint128 },
+
+    #[error("Only the surplus of {surplus} {token} can be swept")]
+    SurplusExceeded { token: String, surplus: Uint128 },
+
+    #[error("Block size must be at least 1")]
+    InvalidBlockSize {},
+
+    #[error("Queued transfers are released after the epoch ends at {ends}")]
+    EpochNotOver { ends: Timestamp },
+
+    #[error("No queued transfer {id} of the sender")]
+    NoSuchQueuedTransfer { id: u64 },
+
+    #[error("Batch epoch must be at least one second")]
+    InvalidEpoch {},
+
+    #[error("Invalid decoy config: {reason}")]
+    InvalidDecoyConfig { reason: String },
+
+    #[error("Token {token} is not a usable SNIP20: {reason}")]
+    InvalidToken { token: String, reason: String },
+
+    #[error("Invalid token route: {reason}")]
+    InvalidTokenRoute { reason: String },
+
+    #[error("Channel {channel} does not lead to chain {chain}")]
+    ChannelNotOnChain { channel: String, chain: String },
+
+    #[error("The sender of the packet is not known")]
+    UnknownPacketSender {},
+
+    #[error("Token {token} cannot be transferred while {status:?}")]
+    TokenNotActive { token: String, status: TokenStatus },
+
+    #[error("No quota override for {address} on {token}")]
+    NoSuchQuotaOverride { token: String, address: String },
+}
+
+impl ContractError {
+    /// Stable numeric code of this error, reported in error acknowledgements and events.
+    /// Codes are never renumbered or reused; new variants take the next free number.
+    pub fn code(&self) -> u32 {
+        match self {
+            ContractError::Std(_) => 1,
+            ContractError::UnregisteredAsset { .. } => 2,
+            ContractError::MissingTransferMsg {} => 3,
+            ContractError::NoSuchChannel { .. } => 4,
+            ContractError::NoFunds {} => 5,
+            ContractError::AmountOverflow {} => 6,
+            ContractError::InvalidIbcVersion { .. } => 7,
+            ContractError::OnlyOrderedChannel {} => 8,
+            ContractError::InsufficientFunds { .. } => 9,
+            ContractError::NoForeignTokens {} => 10,
+            ContractError::OnlySecretTokens {} => 11,
+            ContractError::FromOtherPort { .. } => 12,
+            ContractError::FromOtherChannel { .. } => 13,
+            ContractError::CannotMigrate { .. } => 14,
+            ContractError::CannotMigrateVersion { .. } => 15,
+            ContractError::UnknownReplyId { .. } => 16,
+            ContractError::CannotLowerGas => 17,
+            ContractError::Unauthorized => 18,
+            ContractError::NotOnAllowList => 19,
+            ContractError::Snip20TransferFailed { .. } => 20,
+            ContractError::Paused { .. } => 21,
+            ContractError::RateLimitExceeded { .. } => 22,
+            ContractError::InvalidQuota { .. } => 23,
+            ContractError::NoSuchQuota { .. } => 24,
+            ContractError::AddressQuotaExceeded { .. } => 25,
+            ContractError::EscrowCapExceeded { .. } => 26,
+            ContractError::AmountBelowMinimum { .. } => 27,
+            ContractError::InvalidFeeConfig { .. } => 28,
+            ContractError::ConnectionNotAllowed { .. } => 29,
+            ContractError::CounterpartyPortNotAllowed { .. } => 30,
+            ContractError::ChannelNotPermitted { .. } => 31,
+            ContractError::UnknownChain { .. } => 32,
+            ContractError::InvalidRemoteAddress { .. } => 33,
+            ContractError::NoTransferTarget {} => 34,
+            ContractError::InvalidChainInfo { .. } => 35,
+            ContractError::ChannelRetired { .. } => 36,
+            ContractError::InvalidMigration { .. } => 37,
+            ContractError::MigrationLocked { .. } => 38,
+            ContractError::EscrowShort { .. } => 39,
+            ContractError::SurplusExceeded { .. } => 40,
+            ContractError::InvalidBlockSize {} => 41,
+            ContractError::EpochNotOver { .. } => 42,
+            ContractError::NoSuchQueuedTransfer { .. } => 43,
+            ContractError::InvalidEpoch {} => 44,
+            ContractError::InvalidDecoyConfig { .. } => 45,
+            ContractError::InvalidToken { .. } => 46,
+            ContractError::InvalidTokenRoute { .. } => 47,
+            ContractError::ChannelNotOnChain { .. } => 48,
+            // 49 was IdenticalTransferInFlight
+            ContractError::UnknownPacketSender {} => 50,
+            ContractError::TokenNotActive { .. } => 51,
+            ContractError::NoSuchQuotaOverride { .. } => 52,
+        }
+    }
+
+    /// Coarse grouping of the error, so alerting can key on a kind of failure
+    pub fn category(&self) -> &'static str {
+        match self {
+            ContractError::Std(_) => "internal",
+            ContractError::UnregisteredAsset { .. }
+            | ContractError::NoForeignTokens {}
+            | ContractError::OnlySecretTokens {}
+            | ContractError::NotOnAllowList
+            | ContractError::CannotLowerGas => "asset",
+            ContractError::MissingTransferMsg {}
+            | ContractError::NoFunds {}
+            | ContractError::AmountOverflow {}
+            | ContractError::AmountBelowMinimum { .. }
+            | ContractError::InvalidRemoteAddress { .. }
+            | ContractError::NoTransferTarget {}
+            | ContractError::UnknownPacketSender {} => "packet",
+            ContractError::NoSuchChannel { .. }
+            | ContractError::InvalidIbcVersion { .. }
+            | ContractError::OnlyOrderedChannel {}
+            | ContractError::FromOtherPort { .. }
+            | ContractError::FromOtherChannel { .. }
+            | ContractError::ConnectionNotAllowed { .. }
+            | ContractError::CounterpartyPortNotAllowed { .. }
+            | ContractError::ChannelNotPermitted { .. }
+            | ContractError::UnknownChain { .. }
+            | ContractError::ChannelNotOnChain { .. }
+            | ContractError::ChannelRetired { .. }
+            | ContractError::InvalidMigration { .. }
+            | ContractError::MigrationLocked { .. } => "channel",
+            ContractError::InsufficientFunds { .. }
+            | ContractError::EscrowCapExceeded { .. }
+            | ContractError::EscrowShort { .. }
+            | ContractError::SurplusExceeded { .. } => "balance",
+            ContractError::CannotMigrate { .. }
+            | ContractError::CannotMigrateVersion { .. }
+            | ContractError::UnknownReplyId { .. } => "contract",
+            ContractError::Unauthorized => "auth",
+            ContractError::Snip20TransferFailed { .. }
+            | ContractError::InvalidToken { .. }
+            | ContractError::TokenNotActive { .. } => "token",
+            ContractError::Paused { .. } => "paused",
+            ContractError::EpochNotOver { .. } | ContractError::NoSuchQueuedTransfer { .. } => {
+                "batch"
+            }
+            ContractError::RateLimitExceeded { .. }
+            | ContractError::AddressQuotaExceeded { .. } => "rate_limit",
+            ContractError::InvalidQuota { .. }
+            | ContractError::NoSuchQuota { .. }
+            | ContractError::NoSuchQuotaOverride { .. }
+            | ContractError::InvalidFeeConfig { .. }
+            | ContractError::InvalidChainInfo { .. }
+            | ContractError::InvalidBlockSize {}
+            | ContractError::InvalidEpoch {}
+            | ContractError::InvalidDecoyConfig { .. }
+            | ContractError::InvalidTokenRoute { .. } => "config",
+        }
+    }
 }
 
 impl From<FromUtf8Error> for ContractError {
@@ -0,0 +1,360 @@
+use cosmwasm_std::{Addr, StdResult, Storage, Uint128};
+
+use crate::error::ContractError;
+use crate::state::{
+    FeeConfig, FeeSchedule, RelayerIncentive, RelayerStats, ACCRUED_FEES, PACKET_FEES,
+    RELAYER_REWARDS, RELAYER_STATS, TOKEN_FEES,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Delivery {
+    Receive,
+    Ack,
+    Timeout,
+}
+
+const BPS_DENOMINATOR: u32 = 10_000;
+
+impl FeeSchedule {
+    pub fn validate(&self) -> Result<(), ContractError> {
+        if u32::from(self.bps) > BPS_DENOMINATOR {
+            return Err(ContractError::InvalidFeeConfig {
+                reason: "bps cannot exceed 10000".into(),
+            });
+        }
+        if let Some(max_fee) = self.max_fee {
+            if max_fee < self.min_fee {
+                return Err(ContractError::InvalidFeeConfig {
+                    reason: "max_fee is below min_fee".into(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// The fee charged on a transfer of amount, rejecting dust and transfers the fee would eat
+    pub fn fee_for(&self, amount: Uint128) -> Result<Uint128, ContractError> {
+        if amount < self.min_transfer {
+            return Err(ContractError::AmountBelowMinimum {
+                min: self.min_transfer,
+            });
+        }
+        let mut fee = amount
+            .multiply_ratio(self.bps, BPS_DENOMINATOR)
+            .max(self.min_fee);
+        if let Some(max_fee) = self.max_fee {
+            fee = fee.min(max_fee);
+        }
+        if fee >= amount {
+            return Err(ContractError::AmountBelowMinimum {
+                min: fee + Uint128::new(1),
+            });
+        }
+        Ok(fee)
+    }
+}
+
+impl FeeConfig {
+    pub fn validate(&self) -> Result<(), ContractError> {
+        self.default.validate()?;
+        if self.refund_fee && !self.accrue {
+            return Err(ContractError::InvalidFeeConfig {
+                reason: "fees can only be refunded while they accrue in the contract".into(),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// The fee schedule that applies to token
+pub fn fee_schedule(
+    storage: &dyn Storage,
+    config: &FeeConfig,
+    token: &str,
+) -> StdResult<FeeSchedule> {
+    Ok(TOKEN_FEES
+        .may_load(storage, token)?
+        .unwrap_or_else(|| config.default.clone()))
+}
+
+pub fn accrue_fee(storage: &mut dyn Storage, token: &str, fee: Uint128) -> StdResult<()> {
+    ACCRUED_FEES.update(storage, token, |accrued| -> StdResult<_> {
+        Ok(accrued.unwrap_or_default() + fee)
+    })?;
+    Ok(())
+}
+
+/// Takes up to amount out of the accrued fees of token, returning what could be taken
+pub fn take_accrued_fee(
+    storage: &mut dyn Storage,
+    token: &str,
+    amount: Uint128,
+) -> StdResult<Uint128> {
+    let accrued = ACCRUED_FEES.may_load(storage, token)?.unwrap_or_default();
+    let taken = accrued.min(amount);
+    ACCRUED_FEES.save(storage, token, &(accrued - taken))?;
+    Ok(taken)
+}
+
+/// Holds on to the fee of a packet we send until it is acknowledged or times out
+pub fn hold_packet_fee(
+    storage: &mut dyn Storage,
+    channel: &str,
+    packet_key: &[u8],
+    fee: Uint128,
+) -> StdResult<()> {
+    let mut fees = PACKET_FEES
+        .may_load(storage, (channel, packet_key))?
+        .unwrap_or_default();
+    fees.push(fee);
+    PACKET_FEES.save(storage, (channel, packet_key), &fees)
+}
+
+/// The fee held for the packet, zero if none was charged
+pub fn release_packet_fee(
+    storage: &mut dyn Storage,
+    channel: &str,
+    packet_key: &[u8],
+) -> StdResult<Uint128> {
+    let mut fees = match PACKET_FEES.may_load(storage, (channel, packet_key))? {
+        Some(fees) => fees,
+        None => return Ok(Uint128::zero()),
+    };
+    let fee = fees.remove(0);
+    if fees.is_empty() {
+        PACKET_FEES.remove(storage, (channel, packet_key));
+    } else {
+        PACKET_FEES.save(storage, (channel, packet_key), &fees)?;
+    }
+    Ok(fee)
+}
+
+/// Takes back a reward paid for a delivery that failed after all, returning it to the fees
+pub fn undo_relayer_reward(
+    storage: &mut dyn Storage,
+    relayer: &Addr,
+    token: &str,
+    reward: Uint128,
+) -> StdResult<()> {
+    if reward.is_zero() {
+        return Ok(());
+    }
+    RELAYER_REWARDS.update(storage, (relayer, token), |owed| -> StdResult<_> {
+        Ok(owed.unwrap_or_default().saturating_sub(reward))
+    })?;
+    accrue_fee(storage, token, reward)
+}
+
+impl RelayerIncentive {
+    /// Rewards are paid out of the accrued fees, so without accrual there is nothing to pay them from
+    pub fn validate(&self, fees: &FeeConfig) -> Result<(), ContractError> {
+        if u32::from(self.fee_share_bps) > BPS_DENOMINATOR {
+            return Err(ContractError::InvalidFeeConfig {
+                reason: "fee_share_bps cannot exceed 10000".into(),
+            });
+        }
+        if !fees.accrue && *self != RelayerIncentive::default() {
+            return Err(ContractError::InvalidFeeConfig {
+                reason: "relayers can only be rewarded while fees accrue in the contract".into(),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Counts a packet the relayer delivered to us
+pub fn record_delivery(
+    storage: &mut dyn Storage,
+    relayer: &Addr,
+    delivery: Delivery,
+) -> StdResult<()> {
+    RELAYER_STATS.update(storage, relayer, |stats| -> StdResult<_> {
+        let mut stats: RelayerStats = stats.unwrap_or_default();
+        match delivery {
+            Delivery::Receive => stats.receives += 1,
+            Delivery::Ack => stats.acks += 1,
+            Delivery::Timeout => stats.timeouts += 1,
+        }
+        Ok(stats)
+    })?;
+    Ok(())
+}
+
+/// Credits the relayer with its reward for delivering a packet of token that was charged
+/// packet_fee. The reward comes out of the accrued fees, so it is capped by what is left there.
+/// The flat reward of a receive is taken from fees of earlier transfers, out of what the
+/// collector can claim.
+pub fn reward_relayer(
+    storage: &mut dyn Storage,
+    incentive: &RelayerIncentive,
+    relayer: &Addr,
+    token: &str,
+    packet_fee: Uint128,
+) -> StdResult<Uint128> {
+    let reward =
+        packet_fee.multiply_ratio(incentive.fee_share_bps, BPS_DENOMINATOR) + incentive.flat;
+    if reward.is_zero() {
+        return Ok(reward);
+    }
+    let reward = take_accrued_fee(storage, token, reward)?;
+    if !reward.is_zero() {
+        RELAYER_REWARDS.update(storage, (relayer, token), |owed| -> StdResult<_> {
+            Ok(owed.unwrap_or_default() + reward)
+        })?;
+    }
+    Ok(reward)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cosmwasm_std::testing::MockStorage;
+
+    fn schedule() -> FeeSchedule {
+        FeeSchedule {
+            bps: 30,
+            min_fee: Uint128::new(10),
+            max_fee: Some(Uint128::new(1_000)),
+            min_transfer: Uint128::new(100),
+        }
+    }
+
+    #[test]
+    fn fee_is_bounded_by_min_and_max() {
+        let schedule = schedule();
+        assert_eq!(
+            schedule.fee_for(Uint128::new(100)).unwrap(),
+            Uint128::new(10)
+        );
+        assert_eq!(
+            schedule.fee_for(Uint128::new(100_000)).unwrap(),
+            Uint128::new(300)
+        );
+        assert_eq!(
+            schedule.fee_for(Uint128::new(10_000_000)).unwrap(),
+            Uint128::new(1_000)
+        );
+    }
+
+    #[test]
+    fn dust_and_fee_eaten_transfers_are_rejected() {
+        let mut schedule = schedule();
+        assert_eq!(
+            schedule.fee_for(Uint128::new(99)).unwrap_err(),
+            ContractError::AmountBelowMinimum {
+                min: Uint128::new(100)
+            }
+        );
+
+        schedule.min_transfer = Uint128::zero();
+        assert_eq!(
+            schedule.fee_for(Uint128::new(10)).unwrap_err(),
+            ContractError::AmountBelowMinimum {
+                min: Uint128::new(11)
+            }
+        );
+    }
+
+    #[test]
+    fn invalid_schedules_are_rejected() {
+        let mut schedule = schedule();
+        schedule.bps = 10_001;
+        assert!(schedule.validate().is_err());
+
+        let mut schedule = self::schedule();
+        schedule.max_fee = Some(Uint128::new(5));
+        assert!(schedule.validate().is_err());
+
+        let config = FeeConfig {
+            default: self::schedule(),
+            collector: None,
+            accrue: false,
+            refund_fee: true,
+        };
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn packet_fees_are_released_in_order() {
+        let mut storage = MockStorage::new();
+        hold_packet_fee(&mut storage, "channel-0", b"packet", Uint128::new(5)).unwrap();
+        hold_packet_fee(&mut storage, "channel-0", b"packet", Uint128::new(7)).unwrap();
+
+        let release = |storage: &mut MockStorage| {
+            release_packet_fee(storage, "channel-0", b"packet").unwrap()
+        };
+        assert_eq!(release(&mut storage), Uint128::new(5));
+        assert_eq!(release(&mut storage), Uint128::new(7));
+        // nothing held, nothing charged
+        assert_eq!(release(&mut storage), Uint128::zero());
+    }
+
+    #[test]
+    fn relayers_are_only_rewarded_while_fees_accrue() {
+        let mut fees = FeeConfig {
+            default: schedule(),
+            collector: Some(Addr::unchecked("collector")),
+            accrue: false,
+            refund_fee: false,
+        };
+        let incentive = RelayerIncentive {
+            fee_share_bps: 0,
+            flat: Uint128::new(1),
+        };
+        assert!(incentive.validate(&fees).is_err());
+        RelayerIncentive::default().validate(&fees).unwrap();
+
+        fees.accrue = true;
+        incentive.validate(&fees).unwrap();
+    }
+
+    #[test]
+    fn relayer_rewards_come_out_of_accrued_fees() {
+        let mut storage = MockStorage::new();
+        let relayer = Addr::unchecked("relayer");
+        let incentive = RelayerIncentive {
+            fee_share_bps: 5_000,
+            flat: Uint128::new(2),
+        };
+        accrue_fee(&mut storage, "token", Uint128::new(10)).unwrap();
+
+        let reward = reward_relayer(
+            &mut storage,
+            &incentive,
+            &relayer,
+            "token",
+            Uint128::new(10),
+        )
+        .unwrap();
+        assert_eq!(reward, Uint128::new(7));
+        assert_eq!(
+            ACCRUED_FEES.load(&storage, "token").unwrap(),
+            Uint128::new(3)
+        );
+
+        // capped by what is left
+        let reward = reward_relayer(
+            &mut storage,
+            &incentive,
+            &relayer,
+            "token",
+            Uint128::new(10),
+        )
+        .unwrap();
+        assert_eq!(reward, Uint128::new(3));
+        assert_eq!(
+            RELAYER_REWARDS.load(&storage, (&relayer, "token")).unwrap(),
+            Uint128::new(10)
+        );
+
+        undo_relayer_reward(&mut storage, &relayer, "token", Uint128::new(3)).unwrap();
+        assert_eq!(
+            RELAYER_REWARDS.load(&storage, (&relayer, "token")).unwrap(),
+            Uint128::new(7)
+        );
+        assert_eq!(
+            ACCRUED_FEES.load(&storage, "token").unwrap(),
+            Uint128::new(3)
+        );
+    }
+}
@@ -0,0 +1,69 @@
+use cosmwasm_std::{Addr, Order, StdResult, Storage, Timestamp, Uint128};
+
+use crate::state::{HistoryAction, HistoryEntry, USER_HISTORY, USER_HISTORY_COUNT};
+
+/// Larger pages are cut down to this many entries, so a query's gas stays bounded
+pub const MAX_PAGE_SIZE: u32 = 100;
+
+/// Adds an entry to the user's history and returns its id
+#[allow(clippy::too_many_arguments)]
+pub fn record_history(
+    storage: &mut dyn Storage,
+    user: &Addr,
+    action: HistoryAction,
+    token: &str,
+    amount: Uint128,
+    channel: &str,
+    counterparty: &str,
+    time: Timestamp,
+) -> StdResult<u64> {
+    let id = USER_HISTORY_COUNT
+        .may_load(storage, user)?
+        .unwrap_or_default();
+    let entry = HistoryEntry {
+        id,
+        action,
+        token: token.to_string(),
+        amount,
+        channel: channel.to_string(),
+        counterparty: counterparty.to_string(),
+        time,
+    };
+    USER_HISTORY.save(storage, (user, id), &entry)?;
+    USER_HISTORY_COUNT.save(storage, user, &(id + 1))?;
+    Ok(id)
+}
+
+/// Removes the user's latest entry, for a receive or refund whose token transfer failed
+pub fn undo_record_history(storage: &mut dyn Storage, user: &Addr) -> StdResult<()> {
+    let count = USER_HISTORY_COUNT
+        .may_load(storage, user)?
+        .unwrap_or_default();
+    if count > 0 {
+        USER_HISTORY.remove(storage, (user, count - 1));
+        USER_HISTORY_COUNT.save(storage, user, &(count - 1))?;
+    }
+    Ok(())
+}
+
+/// Returns one page of the user's history, newest first, along with the total number of entries.
+/// Pages hold at most MAX_PAGE_SIZE entries.
+pub fn history_page(
+    storage: &dyn Storage,
+    user: &Addr,
+    page: u32,
+    page_size: u32,
+) -> StdResult<(Vec<HistoryEntry>, u64)> {
+    let page_size = page_size.min(MAX_PAGE_SIZE);
+    let total = USER_HISTORY_COUNT
+        .may_load(storage, user)?
+        .unwrap_or_default();
+    let entries = USER_HISTORY
+        .prefix(user)
+        .range(storage, None, None, Order::Descending)
+        .skip(page as usize * page_size as usize)
+        .take(page_size as usize)
+        .map(|entry| entry.map(|(_, entry)| entry))
+        .collect::<StdResult<_>>()?;
+    Ok((entries, total))
+}
@@ -1,24 +1,58 @@
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
 use cosmwasm_std::{
-    attr, entry_point, from_binary, to_binary, Addr, Binary, CosmosMsg, DepsMut, Env,
-    Ibc3ChannelOpenResponse, IbcBasicResponse, IbcChannel, IbcChannelCloseMsg,
+    attr, entry_point, from_binary, from_slice, to_binary, Addr, Attribute, Binary, CosmosMsg,
+    Deps, DepsMut, Env, Ibc3ChannelOpenResponse, IbcBasicResponse, IbcChannel, IbcChannelCloseMsg,
     IbcChannelConnectMsg, IbcChannelOpenMsg, IbcEndpoint, IbcOrder, IbcPacket, IbcPacketAckMsg,
-    IbcPacketReceiveMsg, IbcPacketTimeoutMsg, IbcReceiveResponse, Reply, Response, SubMsg,
-    SubMsgResult, Uint128, WasmMsg,
+    IbcPacketReceiveMsg, IbcPacketTimeoutMsg, IbcReceiveResponse, Reply, Response, StdResult,
+    Storage, SubMsg, SubMsgResult, Uint128, WasmMsg,
 };
 
 use crate::amount::Snip20Coin;
+use crate::decoys::{pick_decoys, record_recent_user};
 use crate::error::{ContractError, Never};
+use crate::fees::{
+    record_delivery, release_packet_fee, reward_relayer, take_accrued_fee, undo_relayer_reward,
+    Delivery,
+};
+use crate::history::{record_history, undo_record_history};
+use crate::notifications::{
+    notify, notify_transfer, transfer_status_data, TransferStatus, TRANSFER_STATUS_CHANNEL,
+};
+use crate::padding::pad_binary;
+use crate::rate_limit::{check_and_update_flow, undo_address_usage, undo_flow, FlowType};
+use crate::tokens::{
+    assert_token_active, flag_failing_token, on_token_registered, on_viewing_key_set,
+};
 
 use crate::state::{
-    reduce_channel_balance, undo_reduce_channel_balance, ChannelInfo, ReplyArgs, CHANNEL_INFO,
-    CODE_HASH, REPLY_ARGS,
+    active_channel, reduce_channel_balance, release_packet_sender, undo_reduce_channel_balance,
+    ChannelInfo, Config, HistoryAction, ReplyArgs, ALLOWED_CONNECTIONS, ALLOWED_COUNTERPARTY_PORTS,
+    CHANNEL_INFO, CODE_HASH, CONFIG, FAILED_RELEASES, PREDECESSORS, QUEUED_TRANSFERS, REPLY_ARGS,
 };
 
 pub const ICS20_VERSION: &str = "ics20-1";
 pub const ICS20_ORDERING: IbcOrder = IbcOrder::Unordered;
+pub const ICS29_VERSION: &str = "ics29-1";
+
+/// The version of a channel wrapped by the ICS-29 fee middleware.
+/// Defined here: https://github.com/cosmos/ibc-go/blob/v4.0.0/modules/apps/29-fee/types/metadata.pb.go#L26-L31
+#[derive(Serialize, Deserialize, Clone, Eq, PartialEq, JsonSchema, Debug)]
+pub struct FeeVersion {
+    pub fee_version: String,
+    pub app_version: String,
+}
+
+/// The acknowledgement of a packet on a fee enabled channel, wrapping the one of the app.
+/// Defined here: https://github.com/cosmos/ibc-go/blob/v4.0.0/proto/ibc/applications/fee/v1/ack.proto
+#[derive(Serialize, Deserialize, Clone, Eq, PartialEq, JsonSchema, Debug)]
+pub struct IncentivizedAcknowledgement {
+    pub app_acknowledgement: Binary,
+    pub forward_relayer_address: String,
+    pub underlying_app_success: bool,
+}
 
 /// The format for sending an ics20 packet.
 /// Proto defined here: https://github.com/cosmos/cosmos-sdk/blob/v0.42.0/proto/ibc/applications/transfer/v1/transfer.proto#L11-L20
@@ -35,6 +69,12 @@ pub struct Ics20Packet {
     pub sender: String,
 }
 
+/// Identifies a packet we sent by what it carries, as the chain only assigns its sequence
+/// once it is sent
+pub fn packet_key(data: &Binary) -> Vec<u8> {
+    Sha256::digest(data.as_slice()).to_vec()
+}
+
 impl Ics20Packet {
     pub fn new<T: Into<String>>(amount: Uint128, denom: T, sender: &str, receiver: &str) -> Self {
         Ics20Packet {
@@ -72,20 +112,62 @@ fn ack_success() -> Binary {
     to_binary(&res).unwrap()
 }
 
-// create a serialized error message
-fn ack_fail(err: String) -> Binary {
-    let res = Ics20Ack::Error(err);
+// create a serialized error message in the "ABCI code N: message" format, so the
+// counterparty can tell failures apart without parsing the free-form text
+fn ack_fail(err: &ContractError) -> Binary {
+    let res = Ics20Ack::Error(format!("ABCI code {}: {}", err.code(), err));
     to_binary(&res).unwrap()
 }
 
+// event attributes describing an error, alerting keys on code and category
+fn error_attributes(err: &ContractError) -> Vec<Attribute> {
+    vec![
+        attr("error", err.to_string()),
+        attr("error_code", err.code().to_string()),
+        attr("error_category", err.category()),
+    ]
+}
+
+// attributes that tell who moved how much, left out of events when private_events is set
+const PRIVATE_ATTRIBUTES: [&str; 7] = [
+    "sender",
+    "receiver",
+    "denom",
+    "amount",
+    "fee",
+    "refund",
+    "relayer_reward",
+];
+
+pub fn redact_attributes(config: &Config, attributes: &mut Vec<Attribute>) {
+    if config.private_events {
+        attributes.retain(|a| !PRIVATE_ATTRIBUTES.contains(&a.key.as_str()));
+    }
+}
+
 const RECEIVE_ID: u64 = 1337;
 const ACK_FAILURE_ID: u64 = 0xfa17;
+// each range of reply ids below holds 2^56 ids, more than queue ids can ever count up to
+/// queued transfers are released with this plus their queue id as reply id
+pub const RELEASE_ID_OFFSET: u64 = 1 << 56;
+/// register_receive messages reply with this plus their index in the registration
+pub const REGISTER_ID_OFFSET: u64 = 2 << 56;
+/// set_viewing_key messages of a registration reply with this plus the token's index in it
+pub const VIEWING_KEY_ID_OFFSET: u64 = 3 << 56;
+/// refunds of failed releases reply with this plus the queue id of the transfer
+const RELEASE_REFUND_ID_OFFSET: u64 = 4 << 56;
 
 #[entry_point]
-pub fn reply(deps: DepsMut, _env: Env, reply: Reply) -> Result<Response, ContractError> {
+pub fn reply(deps: DepsMut, env: Env, reply: Reply) -> Result<Response, ContractError> {
     match reply.id {
         RECEIVE_ID => match reply.result {
-            SubMsgResult::Ok(_) => Ok(Response::new()),
+            SubMsgResult::Ok(_) => {
+                // only now the tokens are unlocked, so only now the receiver is told
+                let reply_args = REPLY_ARGS.load(deps.storage)?;
+                let notification =
+                    notify_transfer(deps, &env, &reply_args, TransferStatus::Received)?;
+                Ok(Response::new().add_attributes(vec![notification]))
+            }
             SubMsgResult::Err(err) => {
                 // Important design note:  with ibcv2 and wasmd 0.22 we can implement this all much easier.
                 // No reply needed... the receive function and submessage should return error on failure and all
@@ -106,15 +188,112 @@ pub fn reply(deps: DepsMut, _env: Env, reply: Reply) -> Result<Response, Contrac
                     &reply_args.denom,
                     reply_args.amount,
                 )?;
+                undo_flow(
+                    deps.storage,
+                    env.block.time,
+                    &reply_args.channel,
+                    &reply_args.denom,
+                    reply_args.amount,
+                    FlowType::In,
+                )?;
+                undo_record_history(deps.storage, &reply_args.receiver)?;
+                // the error ack pays no reward, as when the packet is rejected outright
+                if let Some(relayer) = &reply_args.relayer {
+                    undo_relayer_reward(
+                        deps.storage,
+                        relayer,
+                        &reply_args.denom,
+                        reply_args.relayer_reward,
+                    )?;
+                }
+                flag_failing_token(deps.storage, &Addr::unchecked(&reply_args.denom))?;
 
+                let ack_error = err.clone();
+                let err = ContractError::Snip20TransferFailed { reason: err };
                 Ok(Response::new()
-                    .add_attribute("ack_error", &err)
-                    .set_data(ack_fail(err)))
+                    .add_attribute("ack_error", ack_error)
+                    .add_attributes(error_attributes(&err))
+                    .set_data(ack_fail(&err)))
             }
         },
         ACK_FAILURE_ID => match reply.result {
+            SubMsgResult::Ok(_) => {
+                let reply_args = REPLY_ARGS.load(deps.storage)?;
+                let notification =
+                    notify_transfer(deps, &env, &reply_args, TransferStatus::Refunded)?;
+                Ok(Response::new().add_attributes(vec![notification]))
+            }
+            SubMsgResult::Err(err) => {
+                // the refund never reached the sender, so drop it from their history
+                let reply_args = REPLY_ARGS.load(deps.storage)?;
+                undo_record_history(deps.storage, &reply_args.receiver)?;
+                flag_failing_token(deps.storage, &Addr::unchecked(&reply_args.denom))?;
+
+                let err = ContractError::Snip20TransferFailed { reason: err };
+                Ok(Response::new()
+                    .add_attributes(error_attributes(&err))
+                    .set_data(ack_fail(&err)))
+            }
+        },
+        id if id >= RELEASE_REFUND_ID_OFFSET => {
+            let queue_id = id - RELEASE_REFUND_ID_OFFSET;
+            match reply.result {
+                SubMsgResult::Ok(_) => {
+                    FAILED_RELEASES.remove(deps.storage, queue_id);
+                    Ok(Response::new())
+                }
+                SubMsgResult::Err(err) => {
+                    // kept, so the sender can cancel it once the token works again
+                    if let Some(queued) = FAILED_RELEASES.may_load(deps.storage, queue_id)? {
+                        flag_failing_token(deps.storage, &queued.token)?;
+                    }
+                    Ok(Response::new()
+                        .add_attribute("action", "refund_failed")
+                        .add_attribute("queue_id", queue_id.to_string())
+                        .add_attribute("error", err))
+                }
+            }
+        }
+        id if id >= VIEWING_KEY_ID_OFFSET => on_viewing_key_set(deps, id, reply.result),
+        id if id >= REGISTER_ID_OFFSET => on_token_registered(deps, id, reply.result),
+        id if id >= RELEASE_ID_OFFSET => match reply.result {
             SubMsgResult::Ok(_) => Ok(Response::new()),
-            SubMsgResult::Err(err) => Ok(Response::new().set_data(ack_fail(err))),
+            SubMsgResult::Err(err) => {
+                // the release was reverted, so the transfer is still queued: hand it back.
+                // Failing here would revert the whole flush, so the refund is a submessage
+                // of its own whose failure leaves the transfer with FAILED_RELEASES
+                let queue_id = id - RELEASE_ID_OFFSET;
+                let res = Response::new()
+                    .add_attribute("action", "release_failed")
+                    .add_attribute("queue_id", queue_id.to_string())
+                    .add_attribute("error", err);
+                let queued = match QUEUED_TRANSFERS.may_load(deps.storage, queue_id)? {
+                    Some(queued) => queued,
+                    None => return Ok(res),
+                };
+                QUEUED_TRANSFERS.remove(deps.storage, queue_id);
+                match CODE_HASH.may_load(deps.storage, queued.token.clone())? {
+                    Some(code_hash) => {
+                        FAILED_RELEASES.save(deps.storage, queue_id, &queued)?;
+                        let config = CONFIG.load(deps.storage)?;
+                        let refund = transfer_amount(
+                            queued.token.into_string(),
+                            code_hash,
+                            queued.sender.into_string(),
+                            queued.amount,
+                            config.block_size,
+                        );
+                        Ok(res.add_submessage(SubMsg::reply_always(
+                            refund,
+                            RELEASE_REFUND_ID_OFFSET + queue_id,
+                        )))
+                    }
+                    // nothing we know how to send back, so the entry is dropped
+                    None => Ok(res
+                        .add_attribute("refund", "dropped")
+                        .add_attribute("token", queued.token)),
+                }
+            }
         },
         _ => Err(ContractError::UnknownReplyId { id: reply.id }),
     }
@@ -123,11 +302,12 @@ pub fn reply(deps: DepsMut, _env: Env, reply: Reply) -> Result<Response, Contrac
 #[entry_point]
 /// enforces ordering and versioning constraints
 pub fn ibc_channel_open(
-    _deps: DepsMut,
+    deps: DepsMut,
     _env: Env,
     msg: IbcChannelOpenMsg,
 ) -> Result<Option<Ibc3ChannelOpenResponse>, ContractError> {
     enforce_order_and_version(msg.channel(), msg.counterparty_version())?;
+    enforce_allow_list(deps.as_ref(), msg.channel())?;
     Ok(None)
 }
 
@@ -139,30 +319,30 @@ pub fn ibc_channel_connect(
     msg: IbcChannelConnectMsg,
 ) -> Result<IbcBasicResponse, ContractError> {
     // we need to check the counter party version in try and ack (sometimes here)
-    enforce_order_and_version(msg.channel(), msg.counterparty_version())?;
+    let fee_enabled = enforce_order_and_version(msg.channel(), msg.counterparty_version())?;
+    enforce_allow_list(deps.as_ref(), msg.channel())?;
 
     let channel: IbcChannel = msg.into();
     let info = ChannelInfo {
         id: channel.endpoint.channel_id,
         counterparty_endpoint: channel.counterparty_endpoint,
         connection_id: channel.connection_id,
+        fee_enabled,
     };
     CHANNEL_INFO.save(deps.storage, &info.id, &info)?;
 
     Ok(IbcBasicResponse::default())
 }
 
+// returns whether the channel is wrapped by the ICS-29 fee middleware
 fn enforce_order_and_version(
     channel: &IbcChannel,
     counterparty_version: Option<&str>,
-) -> Result<(), ContractError> {
-    if channel.version != ICS20_VERSION {
-        return Err(ContractError::InvalidIbcVersion {
-            version: channel.version.clone(),
-        });
-    }
+) -> Result<bool, ContractError> {
+    let fee_enabled = parse_version(&channel.version)?;
     if let Some(version) = counterparty_version {
-        if version != ICS20_VERSION {
+        // both ends of a fee enabled channel run the middleware
+        if parse_version(version)? != fee_enabled {
             return Err(ContractError::InvalidIbcVersion {
                 version: version.to_string(),
             });
@@ -171,9 +351,48 @@ fn enforce_order_and_version(
     if channel.order != ICS20_ORDERING {
         return Err(ContractError::OnlyOrderedChannel {});
     }
+    Ok(fee_enabled)
+}
+
+// only approved connections and counterparty ports may get a channel, so nobody can open one
+// from a light client they control
+fn enforce_allow_list(deps: Deps, channel: &IbcChannel) -> Result<(), ContractError> {
+    if !ALLOWED_CONNECTIONS.has(deps.storage, &channel.connection_id) {
+        return Err(ContractError::ConnectionNotAllowed {
+            id: channel.connection_id.clone(),
+        });
+    }
+    let port = &channel.counterparty_endpoint.port_id;
+    if !ALLOWED_COUNTERPARTY_PORTS.has(deps.storage, port) {
+        return Err(ContractError::CounterpartyPortNotAllowed { port: port.clone() });
+    }
     Ok(())
 }
 
+// accepts "ics20-1" as is or wrapped in a fee version, returning whether it was wrapped
+fn parse_version(version: &str) -> Result<bool, ContractError> {
+    if version == ICS20_VERSION {
+        return Ok(false);
+    }
+    match from_slice::<FeeVersion>(version.as_bytes()) {
+        Ok(fee) if fee.fee_version == ICS29_VERSION && fee.app_version == ICS20_VERSION => Ok(true),
+        _ => Err(ContractError::InvalidIbcVersion {
+            version: version.to_string(),
+        }),
+    }
+}
+
+// acks on fee enabled channels come wrapped in an IncentivizedAcknowledgement
+fn parse_ack(data: &Binary) -> Result<Ics20Ack, ContractError> {
+    match from_binary::<Ics20Ack>(data) {
+        Ok(ack) => Ok(ack),
+        Err(err) => match from_binary::<IncentivizedAcknowledgement>(data) {
+            Ok(wrapped) => Ok(from_binary(&wrapped.app_acknowledgement)?),
+            Err(_) => Err(err.into()),
+        },
+    }
+}
+
 #[entry_point]
 pub fn ibc_channel_close(
     _deps: DepsMut,
@@ -190,39 +409,41 @@ pub fn ibc_channel_close(
 /// We should not return an error if possible, but rather an acknowledgement of failure
 pub fn ibc_packet_receive(
     deps: DepsMut,
-    _env: Env,
+    env: Env,
     msg: IbcPacketReceiveMsg,
 ) -> Result<IbcReceiveResponse, Never> {
     let packet = msg.packet;
 
-    do_ibc_packet_receive(deps, &packet).or_else(|err| {
+    do_ibc_packet_receive(deps, env, &packet, &msg.relayer).or_else(|err| {
         Ok(IbcReceiveResponse::new()
-            .set_ack(ack_fail(err.to_string()))
-            .add_attributes(vec![
-                attr("action", "receive"),
-                attr("success", "false"),
-                attr("error", err.to_string()),
-            ]))
+            .set_ack(ack_fail(&err))
+            .add_attributes(vec![attr("action", "receive"), attr("success", "false")])
+            .add_attributes(error_attributes(&err)))
     })
 }
 
 // Returns local denom if the denom is an encoded voucher from the expected endpoint
 // Otherwise, error
+// retired_endpoints are the remote ends of channels migrated onto this one, whose vouchers it honors
 fn parse_voucher_denom<'a>(
     voucher_denom: &'a str,
     remote_endpoint: &IbcEndpoint,
+    retired_endpoints: &[IbcEndpoint],
 ) -> Result<&'a str, ContractError> {
     let split_denom: Vec<&str> = voucher_denom.splitn(3, '/').collect();
     if split_denom.len() != 3 {
         return Err(ContractError::NoForeignTokens {});
     }
+    let from_retired = retired_endpoints
+        .iter()
+        .any(|e| split_denom[0] == e.port_id && split_denom[1] == e.channel_id);
     // a few more sanity checks
-    if split_denom[0] != remote_endpoint.port_id {
+    if !from_retired && split_denom[0] != remote_endpoint.port_id {
         return Err(ContractError::FromOtherPort {
             port: split_denom[0].into(),
         });
     }
-    if split_denom[1] != remote_endpoint.channel_id {
+    if !from_retired && split_denom[1] != remote_endpoint.channel_id {
         return Err(ContractError::FromOtherChannel {
             channel: split_denom[1].into(),
         });
@@ -237,51 +458,154 @@ fn parse_voucher_denom<'a>(
     Ok(token_address)
 }
 
+// the bookkeeping of an unlock: the decoys, the relayer's reward and the receiver's history.
+// What fails halfway is undone, only the decoy seed and recent users are left as they are.
+fn record_receive(
+    storage: &mut dyn Storage,
+    env: &Env,
+    config: &Config,
+    relayer: &Addr,
+    mut reply_args: ReplyArgs,
+    sender: &str,
+) -> StdResult<(Vec<Addr>, Uint128)> {
+    let receiver = reply_args.receiver.clone();
+    let decoys = pick_decoys(storage, env, &reply_args.denom, receiver.as_str())?;
+    record_recent_user(storage, &receiver)?;
+
+    let reward = reward_relayer(
+        storage,
+        &config.relayer_incentive,
+        relayer,
+        &reply_args.denom,
+        Uint128::zero(),
+    )?;
+    reply_args.relayer_reward = reward;
+    // we need to save the data to update the balances in reply
+    let recorded = REPLY_ARGS.save(storage, &reply_args).and_then(|_| {
+        record_history(
+            storage,
+            &receiver,
+            HistoryAction::Receive,
+            &reply_args.denom,
+            reply_args.amount,
+            &reply_args.channel,
+            sender,
+            env.block.time,
+        )
+    });
+    if let Err(err) = recorded {
+        undo_relayer_reward(storage, relayer, &reply_args.denom, reward)?;
+        return Err(err);
+    }
+    Ok((decoys, reward))
+}
+
 // this does the work of ibc_packet_receive, we wrap it to turn errors into acknowledgements
 fn do_ibc_packet_receive(
     deps: DepsMut,
+    env: Env,
     packet: &IbcPacket,
+    relayer: &Addr,
 ) -> Result<IbcReceiveResponse, ContractError> {
+    // error acks keep state, so recording first counts failed deliveries as well
+    record_delivery(deps.storage, relayer, Delivery::Receive)?;
+
+    let config = CONFIG.load(deps.storage)?;
+    if config.paused.inbound {
+        return Err(ContractError::Paused {
+            direction: "inbound".into(),
+        });
+    }
+
     let msg: Ics20Packet = from_binary(&packet.data)?;
     let channel = packet.dest.channel_id.clone();
 
     // If the token originated on the remote chain, it looks like "ucosm".
     // If it originated on our chain, it looks like "port/channel/cw20:...".
-    let token_address = parse_voucher_denom(&msg.denom, &packet.src)?;
+    let retired_endpoints = PREDECESSORS
+        .may_load(deps.storage, &channel)?
+        .unwrap_or_default()
+        .iter()
+        .map(|retired| {
+            Ok(CHANNEL_INFO
+                .load(deps.storage, retired)?
+                .counterparty_endpoint)
+        })
+        .collect::<StdResult<Vec<_>>>()?;
+    let token_address = parse_voucher_denom(&msg.denom, &packet.src, &retired_endpoints)?;
+    let receiver = deps.api.addr_validate(&msg.receiver)?;
     let code_hash = CODE_HASH.load(deps.storage, Addr::unchecked(token_address))?;
+    assert_token_active(deps.storage, &Addr::unchecked(token_address))?;
+
+    // quotas are relative to the escrow before the unlock
+    check_and_update_flow(
+        deps.storage,
+        env.block.time,
+        &channel,
+        token_address,
+        msg.amount,
+        FlowType::In,
+    )?;
 
     // make sure we have enough balance for this
-    reduce_channel_balance(deps.storage, &channel, token_address, msg.amount)?;
+    // state is not reverted when we return an error ack, so undo the recorded flow ourselves
+    if let Err(err) = reduce_channel_balance(deps.storage, &channel, token_address, msg.amount) {
+        undo_flow(
+            deps.storage,
+            env.block.time,
+            &channel,
+            token_address,
+            msg.amount,
+            FlowType::In,
+        )?;
+        return Err(err);
+    }
 
-    // we need to save the data to update the balances in reply
+    // an error ack keeps state, so the flow and escrow are given back if the bookkeeping fails
     let reply_args = ReplyArgs {
-        channel,
+        channel: channel.clone(),
         denom: token_address.to_string(),
         amount: msg.amount,
+        receiver,
+        relayer: Some(relayer.clone()),
+        relayer_reward: Uint128::zero(),
+    };
+    let (decoys, reward) = match record_receive(
+        deps.storage,
+        &env,
+        &config,
+        relayer,
+        reply_args,
+        &msg.sender,
+    ) {
+        Ok(recorded) => recorded,
+        Err(err) => {
+            undo_reduce_channel_balance(deps.storage, &channel, token_address, msg.amount)?;
+            undo_flow(
+                deps.storage,
+                env.block.time,
+                &channel,
+                token_address,
+                msg.amount,
+                FlowType::In,
+            )?;
+            return Err(err.into());
+        }
     };
-    REPLY_ARGS.save(deps.storage, &reply_args)?;
-
-    deps.api.debug(&format!(
-        "do_ibc_packet_receive() token={} code_hash={} receiver={} amount={}",
-        token_address,
-        code_hash,
-        msg.receiver.clone(),
-        msg.amount
-    ));
 
-    let transfer = transfer_amount(
+    let transfer = transfer_amount_with_decoys(
         token_address.to_string(),
         code_hash,
         msg.receiver.clone(),
         msg.amount,
+        &decoys,
+        config.block_size,
     );
 
-    deps.api
-        .debug(&format!("do_ibc_packet_receive() transfer={:?}", transfer));
+    // the reply notifies the receiver once the transfer went through
+    let submsg = SubMsg::reply_always(transfer, RECEIVE_ID);
 
-    let submsg = SubMsg::reply_on_error(transfer, RECEIVE_ID);
-
-    let res = IbcReceiveResponse::new()
+    let mut res = IbcReceiveResponse::new()
         .set_ack(ack_success())
         .add_submessage(submsg)
         .add_attribute("action", "receive")
@@ -289,7 +613,9 @@ fn do_ibc_packet_receive(
         .add_attribute("receiver", msg.receiver)
         .add_attribute("denom", token_address)
         .add_attribute("amount", msg.amount)
+        .add_attribute("relayer_reward", reward)
         .add_attribute("success", "true");
+    redact_attributes(&config, &mut res.attributes);
 
     Ok(res)
 }
@@ -298,16 +624,19 @@ fn do_ibc_packet_receive(
 /// check if success or failure and update balance, or return funds
 pub fn ibc_packet_ack(
     deps: DepsMut,
-    _env: Env,
+    env: Env,
     msg: IbcPacketAckMsg,
 ) -> Result<IbcBasicResponse, ContractError> {
     // Design decision: should we trap error like in receive?
     // TODO: unsure... as it is now a failed ack handling would revert the tx and would be
     // retried again and again. is that good?
-    let ics20msg: Ics20Ack = from_binary(&msg.acknowledgement.data)?;
+    let ics20msg = parse_ack(&msg.acknowledgement.data)?;
+    record_delivery(deps.storage, &msg.relayer, Delivery::Ack)?;
     match ics20msg {
-        Ics20Ack::Result(_) => on_packet_success(deps, msg.original_packet),
-        Ics20Ack::Error(err) => on_packet_failure(deps, msg.original_packet, err),
+        Ics20Ack::Result(_) => on_packet_success(deps, env, msg.original_packet, &msg.relayer),
+        Ics20Ack::Error(err) => {
+            on_packet_failure(deps, env, msg.original_packet, err, &msg.relayer)
+        }
     }
 }
 
@@ -315,87 +644,682 @@ pub fn ibc_packet_ack(
 /// return fund to original sender (same as failure in ibc_packet_ack)
 pub fn ibc_packet_timeout(
     deps: DepsMut,
-    _env: Env,
+    env: Env,
     msg: IbcPacketTimeoutMsg,
 ) -> Result<IbcBasicResponse, ContractError> {
     // TODO: trap error like in receive? (same question as ack above)
     let packet = msg.packet;
-    on_packet_failure(deps, packet, "timeout".to_string())
+    record_delivery(deps.storage, &msg.relayer, Delivery::Timeout)?;
+    on_packet_failure(deps, env, packet, "timeout".to_string(), &msg.relayer)
+}
+
+// The real sender of a packet we sent. Packets sent before senders were held carry the real
+// sender, unless the bridge stood in for it, then it is unknown.
+fn take_packet_sender(
+    deps: DepsMut,
+    env: &Env,
+    packet: &IbcPacket,
+    msg: &Ics20Packet,
+) -> StdResult<Option<Addr>> {
+    let held = release_packet_sender(
+        deps.storage,
+        &packet.src.channel_id,
+        &packet_key(&packet.data),
+    )?;
+    if held.is_some() || msg.sender == env.contract.address.as_str() {
+        return Ok(held);
+    }
+    deps.api.addr_validate(&msg.sender).map(Some)
 }
 
 // update the balance stored on this (channel, denom) index
-fn on_packet_success(_deps: DepsMut, packet: IbcPacket) -> Result<IbcBasicResponse, ContractError> {
+fn on_packet_success(
+    mut deps: DepsMut,
+    env: Env,
+    packet: IbcPacket,
+    relayer: &Addr,
+) -> Result<IbcBasicResponse, ContractError> {
     let msg: Ics20Packet = from_binary(&packet.data)?;
+    let token = Snip20Coin::from_parts(msg.denom.clone(), msg.amount).address;
+
+    let fee = release_packet_fee(
+        deps.storage,
+        &packet.src.channel_id,
+        &packet_key(&packet.data),
+    )?;
+    let sender = take_packet_sender(deps.branch(), &env, &packet, &msg)?;
+    let config = CONFIG.load(deps.storage)?;
+    let reward = reward_relayer(
+        deps.storage,
+        &config.relayer_incentive,
+        relayer,
+        &token,
+        fee,
+    )?;
 
     // similar event messages like ibctransfer module
-    let attributes = vec![
+    let mut attributes = vec![
         attr("action", "acknowledge"),
         attr("sender", &msg.sender),
         attr("receiver", &msg.receiver),
         attr("denom", &msg.denom),
         attr("amount", msg.amount),
+        attr("relayer_reward", reward),
         attr("success", "true"),
     ];
+    redact_attributes(&config, &mut attributes);
+
+    if let Some(sender) = sender {
+        attributes.push(notify(
+            deps.storage,
+            deps.api,
+            &env,
+            &sender,
+            TRANSFER_STATUS_CHANNEL,
+            transfer_status_data(
+                TransferStatus::Delivered,
+                &token,
+                msg.amount,
+                &packet.src.channel_id,
+            )?,
+        )?);
+    }
 
     Ok(IbcBasicResponse::new().add_attributes(attributes))
 }
 
 // return the tokens to sender
 fn on_packet_failure(
-    deps: DepsMut,
+    mut deps: DepsMut,
+    env: Env,
     packet: IbcPacket,
     err: String,
+    relayer: &Addr,
 ) -> Result<IbcBasicResponse, ContractError> {
+    // returning an error here fails the relayer's tx, so the refund is retried after unpausing
+    let config = CONFIG.load(deps.storage)?;
+    if config.paused.refunds {
+        return Err(ContractError::Paused {
+            direction: "refunds".into(),
+        });
+    }
+
     let msg: Ics20Packet = from_binary(&packet.data)?;
+    let mut to_send = Snip20Coin::from_parts(msg.denom.clone(), msg.amount);
+
+    // the packet may carry a stand-in for the sender, then we kept the real one
+    let sender = take_packet_sender(deps.branch(), &env, &packet, &msg)?
+        .ok_or(ContractError::UnknownPacketSender {})?;
 
-    // undo the balance update on failure (as we pre-emptively added it on send)
-    reduce_channel_balance(deps.storage, &packet.src.channel_id, &msg.denom, msg.amount)?;
+    // undo the balance update on failure (as we pre-emptively added it on send).
+    // If the channel was retired since, its escrow now sits with the successor
+    let escrow_channel = active_channel(deps.storage, &packet.src.channel_id)?;
+    reduce_channel_balance(deps.storage, &escrow_channel, &to_send.address, msg.amount)?;
 
-    let to_send = Snip20Coin::from_parts(msg.denom.clone(), msg.amount);
+    let fee = release_packet_fee(
+        deps.storage,
+        &packet.src.channel_id,
+        &packet_key(&packet.data),
+    )?;
+    // a refunded fee is gone, so the relayer's share of it is not paid out of other fees
+    let kept_fee = if config.fees.refund_fee {
+        // the collector may have claimed it already, then only the net amount is refunded
+        to_send.amount += take_accrued_fee(deps.storage, &to_send.address, fee)?;
+        Uint128::zero()
+    } else {
+        fee
+    };
+    let reward = reward_relayer(
+        deps.storage,
+        &config.relayer_incentive,
+        relayer,
+        &to_send.address,
+        kept_fee,
+    )?;
+
+    // the tokens never left, so they don't count against the outflow quotas
+    undo_flow(
+        deps.storage,
+        env.block.time,
+        &packet.src.channel_id,
+        &to_send.address,
+        msg.amount,
+        FlowType::Out,
+    )?;
+    undo_address_usage(
+        deps.storage,
+        env.block.time,
+        &to_send.address,
+        sender.as_str(),
+        msg.amount + fee,
+    )?;
     let code_hash = CODE_HASH.load(deps.storage, deps.api.addr_validate(&to_send.address)?)?;
 
-    let sender = deps.api.addr_validate(&msg.sender)?;
-    let send = transfer_amount(
+    record_history(
+        deps.storage,
+        &sender,
+        HistoryAction::Refund,
+        &to_send.address,
+        to_send.amount,
+        &packet.src.channel_id,
+        &msg.receiver,
+        env.block.time,
+    )?;
+    // the reply needs to know whose history to correct if the refund fails
+    let reply_args = ReplyArgs {
+        channel: packet.src.channel_id.clone(),
+        denom: to_send.address.clone(),
+        amount: to_send.amount,
+        receiver: sender.clone(),
+        relayer: None,
+        relayer_reward: Uint128::zero(),
+    };
+    REPLY_ARGS.save(deps.storage, &reply_args)?;
+
+    let decoys = pick_decoys(deps.storage, &env, &to_send.address, sender.as_str())?;
+    let send = transfer_amount_with_decoys(
         to_send.address,
         code_hash,
         sender.into_string(),
         to_send.amount,
+        &decoys,
+        config.block_size,
     );
-    let submsg = SubMsg::reply_on_error(send, ACK_FAILURE_ID);
+    // the reply notifies the sender once the refund went through
+    let submsg = SubMsg::reply_always(send, ACK_FAILURE_ID);
 
     // similar event messages like ibctransfer module
-    let res = IbcBasicResponse::new()
+    let mut res = IbcBasicResponse::new()
         .add_submessage(submsg)
         .add_attribute("action", "acknowledge")
         .add_attribute("sender", msg.sender)
         .add_attribute("receiver", msg.receiver)
         .add_attribute("denom", msg.denom)
         .add_attribute("amount", msg.amount.to_string())
+        .add_attribute("refund", to_send.amount)
+        .add_attribute("relayer_reward", reward)
         .add_attribute("success", "false")
         .add_attribute("error", err);
+    redact_attributes(&config, &mut res.attributes);
 
     Ok(res)
 }
 
-fn transfer_amount(
+pub fn transfer_amount(
     contract_addr: String,
     code_hash: String,
     recipient: String,
     amount: Uint128,
+    block_size: u16,
 ) -> CosmosMsg {
+    transfer_amount_with_decoys(contract_addr, code_hash, recipient, amount, &[], block_size)
+}
+
+// decoys are only understood by SNIP20s that support them, so the field is left out when empty
+pub fn transfer_amount_with_decoys(
+    contract_addr: String,
+    code_hash: String,
+    recipient: String,
+    amount: Uint128,
+    decoys: &[Addr],
+    block_size: u16,
+) -> CosmosMsg {
+    let decoys = if decoys.is_empty() {
+        String::new()
+    } else {
+        let quoted: Vec<String> = decoys.iter().map(|d| format!(r#""{}""#, d)).collect();
+        format!(r#","decoys":[{}]"#, quoted.join(","))
+    };
     WasmMsg::Execute {
         contract_addr,
         code_hash,
-        msg: Binary::from(
-            format!(
-                r#"{{"transfer":{{"recipient":"{}","amount":"{}"}}}}"#,
-                recipient,
-                amount.u128()
-            )
-            .as_bytes()
-            .to_vec(),
+        msg: pad_binary(
+            Binary::from(
+                format!(
+                    r#"{{"transfer":{{"recipient":"{}","amount":"{}"{}}}}}"#,
+                    recipient,
+                    amount.u128(),
+                    decoys
+                )
+                .as_bytes()
+                .to_vec(),
+            ),
+            block_size,
         ),
         funds: vec![],
     }
     .into()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cosmwasm_std::testing::{
+        mock_dependencies, mock_env, mock_ibc_channel, mock_ibc_channel_connect_ack,
+        mock_ibc_packet_ack, mock_ibc_packet_recv, mock_ibc_packet_timeout, mock_info,
+    };
+    use cosmwasm_std::{to_vec, IbcAcknowledgement, ReplyOn, SubMsgResponse};
+
+    use crate::contract::execute;
+    use crate::msg::{ExecuteMsg, SenderMode};
+    use crate::state::{PauseState, TokenDetails, TokenStatus, TOKEN_DETAILS};
+    use crate::test_helpers::{
+        add_channel, add_queued_transfer, add_token, mock_token, receive_msg, send_transfer,
+        sent_packet, setup, snip20_transfer, transfer_msg, ADMIN,
+    };
+
+    fn pause(deps: DepsMut, paused: PauseState) {
+        let mut config = CONFIG.load(deps.storage).unwrap();
+        config.paused = paused;
+        CONFIG.save(deps.storage, &config).unwrap();
+    }
+
+    #[test]
+    fn error_acks_carry_the_error_code() {
+        let err = ContractError::NoFunds {};
+        let ack: Ics20Ack = from_binary(&ack_fail(&err)).unwrap();
+        assert_eq!(ack, Ics20Ack::Error(format!("ABCI code 5: {}", err)));
+    }
+
+    #[test]
+    fn failed_receives_are_acknowledged_with_their_code() {
+        let mut deps = mock_dependencies();
+        setup(deps.as_mut());
+        add_channel(&mut deps.storage, "channel-0", "connection-0");
+
+        // a native denom of the counterparty is nothing we escrowed
+        let packet = Ics20Packet::new(Uint128::new(5), "ucosm", "remote", "alice");
+        let msg = mock_ibc_packet_recv("channel-0", &packet).unwrap();
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), msg).unwrap();
+
+        let err = ContractError::NoForeignTokens {};
+        assert_eq!(res.acknowledgement, ack_fail(&err));
+        assert!(res.attributes.contains(&attr("success", "false")));
+        assert!(res
+            .attributes
+            .contains(&attr("error_code", err.code().to_string())));
+        assert!(res.attributes.contains(&attr("error_category", "asset")));
+    }
+
+    fn fee_version() -> String {
+        String::from_utf8(
+            to_vec(&FeeVersion {
+                fee_version: ICS29_VERSION.into(),
+                app_version: ICS20_VERSION.into(),
+            })
+            .unwrap(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn fee_wrapped_channels_are_recorded_as_fee_enabled() {
+        let mut deps = mock_dependencies();
+        setup(deps.as_mut());
+        // the mock channel runs over connection-2 to their_port
+        ALLOWED_CONNECTIONS
+            .save(&mut deps.storage, "connection-2", &true)
+            .unwrap();
+        ALLOWED_COUNTERPARTY_PORTS
+            .save(&mut deps.storage, "their_port", &true)
+            .unwrap();
+
+        let connect = mock_ibc_channel_connect_ack("channel-0", ICS20_ORDERING, &fee_version());
+        ibc_channel_connect(deps.as_mut(), mock_env(), connect).unwrap();
+        assert!(
+            CHANNEL_INFO
+                .load(&deps.storage, "channel-0")
+                .unwrap()
+                .fee_enabled
+        );
+
+        let connect = mock_ibc_channel_connect_ack("channel-1", ICS20_ORDERING, ICS20_VERSION);
+        ibc_channel_connect(deps.as_mut(), mock_env(), connect).unwrap();
+        assert!(
+            !CHANNEL_INFO
+                .load(&deps.storage, "channel-1")
+                .unwrap()
+                .fee_enabled
+        );
+
+        // both ends run the middleware or neither does
+        let channel = mock_ibc_channel("channel-2", ICS20_ORDERING, &fee_version());
+        let connect = IbcChannelConnectMsg::new_ack(channel, ICS20_VERSION);
+        let err = ibc_channel_connect(deps.as_mut(), mock_env(), connect).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::InvalidIbcVersion {
+                version: ICS20_VERSION.into()
+            }
+        );
+
+        let wrapped_other = fee_version().replace(ICS20_VERSION, "ics20-2");
+        assert!(parse_version(&wrapped_other).is_err());
+    }
+
+    #[test]
+    fn wrapped_error_acks_refund_the_sender() {
+        let mut deps = mock_dependencies();
+        setup(deps.as_mut());
+        add_channel(&mut deps.storage, "channel-0", "connection-0");
+        add_token(&mut deps.storage, "token");
+        let packet = send_transfer(
+            deps.as_mut(),
+            "token",
+            "alice",
+            100,
+            &transfer_msg("channel-0", "remote"),
+        );
+
+        let wrapped = IncentivizedAcknowledgement {
+            app_acknowledgement: ack_fail(&ContractError::NoFunds {}),
+            forward_relayer_address: "relayer".into(),
+            underlying_app_success: false,
+        };
+        let ack = IbcAcknowledgement::new(to_binary(&wrapped).unwrap());
+        let msg = mock_ibc_packet_ack("channel-0", &packet, ack).unwrap();
+        let res = ibc_packet_ack(deps.as_mut(), mock_env(), msg).unwrap();
+
+        assert!(res.attributes.contains(&attr("success", "false")));
+        assert_eq!(
+            snip20_transfer(&res.messages[0].msg),
+            ("token".into(), "alice".into(), Uint128::new(100))
+        );
+    }
+
+    #[test]
+    fn wrapped_success_acks_are_unwrapped() {
+        let wrapped = IncentivizedAcknowledgement {
+            app_acknowledgement: ack_success(),
+            forward_relayer_address: "relayer".into(),
+            underlying_app_success: true,
+        };
+        let ack = parse_ack(&to_binary(&wrapped).unwrap()).unwrap();
+        assert!(matches!(ack, Ics20Ack::Result(_)));
+        assert!(parse_ack(&Binary::from(b"garbage".to_vec())).is_err());
+    }
+
+    // execute pads the attributes of its responses
+    fn has_private_attributes(attributes: &[Attribute]) -> bool {
+        attributes
+            .iter()
+            .any(|a| PRIVATE_ATTRIBUTES.contains(&a.key.trim_end()))
+    }
+
+    #[test]
+    fn private_events_leave_out_who_moved_how_much() {
+        let mut deps = mock_dependencies();
+        setup(deps.as_mut());
+        add_channel(&mut deps.storage, "channel-0", "connection-0");
+        add_token(&mut deps.storage, "token");
+        let msg = receive_msg("alice", 100, &transfer_msg("channel-0", "remote"));
+        let res = execute(deps.as_mut(), mock_env(), mock_info("token", &[]), msg).unwrap();
+        assert!(has_private_attributes(&res.attributes));
+
+        let private = ExecuteMsg::SetPrivateEvents {
+            enabled: true,
+            padding: None,
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), private).unwrap();
+        let msg = receive_msg("alice", 100, &transfer_msg("channel-0", "remote"));
+        let res = execute(deps.as_mut(), mock_env(), mock_info("token", &[]), msg).unwrap();
+        assert!(!has_private_attributes(&res.attributes));
+        assert_eq!(res.attributes[0].value.trim_end(), "transfer");
+
+        let packet = send_transfer(
+            deps.as_mut(),
+            "token",
+            "alice",
+            100,
+            &transfer_msg("channel-0", "remote"),
+        );
+        let msg = mock_ibc_packet_timeout("channel-0", &packet).unwrap();
+        let res = ibc_packet_timeout(deps.as_mut(), mock_env(), msg).unwrap();
+        assert!(!has_private_attributes(&res.attributes));
+        assert!(res.attributes.contains(&attr("success", "false")));
+    }
+
+    #[test]
+    fn sender_modes_decide_who_the_packet_shows() {
+        let mut deps = mock_dependencies();
+        setup(deps.as_mut());
+        add_channel(&mut deps.storage, "channel-0", "connection-0");
+        add_token(&mut deps.storage, "token");
+        let mut env = mock_env();
+        env.contract.address = Addr::unchecked("secret1bridge");
+
+        let mut send = |mode: SenderMode| {
+            let mut msg = transfer_msg("channel-0", "remote");
+            msg.sender_mode = mode;
+            let msg = receive_msg("alice", 100, &msg);
+            let res = execute(deps.as_mut(), env.clone(), mock_info("token", &[]), msg).unwrap();
+            sent_packet(&res).sender
+        };
+        assert_eq!(send(SenderMode::Address), "alice");
+        assert_eq!(send(SenderMode::Contract), "secret1bridge");
+        let pseudonym = send(SenderMode::Pseudonym);
+        assert!(pseudonym.starts_with("secret1"));
+        assert_ne!(send(SenderMode::Pseudonym), pseudonym);
+    }
+
+    #[test]
+    fn identical_packets_refund_their_senders_in_order() {
+        let mut deps = mock_dependencies();
+        setup(deps.as_mut());
+        add_channel(&mut deps.storage, "channel-0", "connection-0");
+        add_token(&mut deps.storage, "token");
+        let mut msg = transfer_msg("channel-0", "remote");
+        msg.sender_mode = SenderMode::Contract;
+
+        // nobody can hold up another's transfer by sending an identical one
+        let first = send_transfer(deps.as_mut(), "token", "alice", 100, &msg);
+        let second = send_transfer(deps.as_mut(), "token", "bob", 100, &msg);
+        assert_eq!(first, second);
+
+        for sender in ["alice", "bob"] {
+            let timeout = mock_ibc_packet_timeout("channel-0", &first).unwrap();
+            let res = ibc_packet_timeout(deps.as_mut(), mock_env(), timeout).unwrap();
+            assert_eq!(
+                snip20_transfer(&res.messages[0].msg),
+                ("token".into(), sender.into(), Uint128::new(100))
+            );
+        }
+        let timeout = mock_ibc_packet_timeout("channel-0", &first).unwrap();
+        let err = ibc_packet_timeout(deps.as_mut(), mock_env(), timeout).unwrap_err();
+        assert_eq!(err, ContractError::UnknownPacketSender {});
+    }
+
+    fn failed(id: u64) -> Reply {
+        Reply {
+            id,
+            result: SubMsgResult::Err("out of gas".into()),
+        }
+    }
+
+    #[test]
+    fn failed_releases_are_refunded_and_kept_until_the_refund_went_through() {
+        let mut deps = mock_dependencies();
+        setup(deps.as_mut());
+        add_token(&mut deps.storage, "token");
+        add_queued_transfer(&mut deps.storage, 7);
+
+        let res = reply(deps.as_mut(), mock_env(), failed(RELEASE_ID_OFFSET + 7)).unwrap();
+        assert!(!QUEUED_TRANSFERS.has(&deps.storage, 7));
+        assert!(FAILED_RELEASES.has(&deps.storage, 7));
+        assert_eq!(res.messages.len(), 1);
+        assert_eq!(res.messages[0].id, RELEASE_REFUND_ID_OFFSET + 7);
+        assert_eq!(res.messages[0].reply_on, ReplyOn::Always);
+        assert_eq!(
+            snip20_transfer(&res.messages[0].msg),
+            ("token".into(), "alice".into(), Uint128::new(100))
+        );
+
+        // a release that already left the queue is not refunded twice
+        let res = reply(deps.as_mut(), mock_env(), failed(RELEASE_ID_OFFSET + 7)).unwrap();
+        assert!(res.messages.is_empty());
+
+        // a failed refund leaves the transfer for its sender to cancel
+        let res = reply(
+            deps.as_mut(),
+            mock_env(),
+            failed(RELEASE_REFUND_ID_OFFSET + 7),
+        )
+        .unwrap();
+        assert!(res.attributes.contains(&attr("action", "refund_failed")));
+        assert!(FAILED_RELEASES.has(&deps.storage, 7));
+
+        let refunded = Reply {
+            id: RELEASE_REFUND_ID_OFFSET + 7,
+            result: SubMsgResult::Ok(SubMsgResponse {
+                events: vec![],
+                data: None,
+            }),
+        };
+        reply(deps.as_mut(), mock_env(), refunded).unwrap();
+        assert!(!FAILED_RELEASES.has(&deps.storage, 7));
+    }
+
+    #[test]
+    fn failed_refunds_hold_the_token_until_its_code_hash_is_updated() {
+        let mut deps = mock_dependencies();
+        setup(deps.as_mut());
+        add_channel(&mut deps.storage, "channel-0", "connection-0");
+        add_token(&mut deps.storage, "token");
+        let token = Addr::unchecked("token");
+        let details = TokenDetails {
+            name: "Token".into(),
+            symbol: "TKN".into(),
+            decimals: 6,
+            total_supply: None,
+            status: TokenStatus::Active,
+        };
+        TOKEN_DETAILS
+            .save(&mut deps.storage, &token, &details)
+            .unwrap();
+
+        let packet = send_transfer(
+            deps.as_mut(),
+            "token",
+            "alice",
+            100,
+            &transfer_msg("channel-0", "remote"),
+        );
+        let msg = mock_ibc_packet_timeout("channel-0", &packet).unwrap();
+        let res = ibc_packet_timeout(deps.as_mut(), mock_env(), msg).unwrap();
+        assert_eq!(res.messages[0].id, ACK_FAILURE_ID);
+        reply(deps.as_mut(), mock_env(), failed(ACK_FAILURE_ID)).unwrap();
+        assert_eq!(
+            TOKEN_DETAILS.load(&deps.storage, &token).unwrap().status,
+            TokenStatus::Failing
+        );
+
+        let transfer = || receive_msg("alice", 100, &transfer_msg("channel-0", "remote"));
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("token", &[]),
+            transfer(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::TokenNotActive {
+                token: "token".into(),
+                status: TokenStatus::Failing,
+            }
+        );
+
+        let update = ExecuteMsg::UpdateTokenCodeHash {
+            token: "token".into(),
+            code_hash: "new-hash".into(),
+            padding: None,
+        };
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("alice", &[]),
+            update.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized);
+
+        mock_token(&mut deps.querier, 0);
+        execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), update).unwrap();
+        assert_eq!(
+            TOKEN_DETAILS.load(&deps.storage, &token).unwrap().status,
+            TokenStatus::Active
+        );
+        assert_eq!(CODE_HASH.load(&deps.storage, token).unwrap(), "new-hash");
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("token", &[]),
+            transfer(),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn releases_of_unknown_tokens_are_dropped_without_failing() {
+        let mut deps = mock_dependencies();
+        setup(deps.as_mut());
+        add_queued_transfer(&mut deps.storage, 3);
+
+        let res = reply(deps.as_mut(), mock_env(), failed(RELEASE_ID_OFFSET + 3)).unwrap();
+        assert!(res.messages.is_empty());
+        assert!(res.attributes.contains(&attr("refund", "dropped")));
+        assert!(!QUEUED_TRANSFERS.has(&deps.storage, 3));
+        assert!(!FAILED_RELEASES.has(&deps.storage, 3));
+    }
+
+    #[test]
+    fn paused_inbound_acknowledges_an_error() {
+        let mut deps = mock_dependencies();
+        setup(deps.as_mut());
+        add_channel(&mut deps.storage, "channel-0", "connection-0");
+        pause(
+            deps.as_mut(),
+            PauseState {
+                inbound: true,
+                ..Default::default()
+            },
+        );
+
+        let packet = Ics20Packet::new(
+            Uint128::new(5),
+            "their-port/channel-1234/token",
+            "remote",
+            "alice",
+        );
+        let msg = mock_ibc_packet_recv("channel-0", &packet).unwrap();
+        let res = ibc_packet_receive(deps.as_mut(), mock_env(), msg).unwrap();
+        let err = ContractError::Paused {
+            direction: "inbound".into(),
+        };
+        assert_eq!(res.acknowledgement, ack_fail(&err));
+    }
+
+    #[test]
+    fn paused_refunds_fail_the_timeout_so_it_is_retried() {
+        let mut deps = mock_dependencies();
+        setup(deps.as_mut());
+        add_channel(&mut deps.storage, "channel-0", "connection-0");
+        pause(
+            deps.as_mut(),
+            PauseState {
+                refunds: true,
+                ..Default::default()
+            },
+        );
+
+        let packet = Ics20Packet::new(Uint128::new(5), "token", "alice", "remote");
+        let msg = mock_ibc_packet_timeout("channel-0", &packet).unwrap();
+        let err = ibc_packet_timeout(deps.as_mut(), mock_env(), msg).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::Paused {
+                direction: "refunds".into()
+            }
+        );
+    }
+}
@@ -1,8 +1,22 @@
 pub mod amount;
 pub mod contract;
+pub mod decoys;
 mod error;
+pub mod fees;
+pub mod history;
 pub mod ibc;
 pub mod msg;
+pub mod notifications;
+pub mod padding;
+pub mod permit;
+pub mod rate_limit;
+pub mod reconcile;
+pub mod registry;
 pub mod state;
+pub mod stats;
+#[cfg(test)]
+mod test_helpers;
+pub mod tokens;
+pub mod viewing_key;
 
 pub use crate::error::ContractError;
@@ -1,6 +1,13 @@
-use cosmwasm_std::{Binary, Uint128};
+use cosmwasm_std::{Addr, Binary, Timestamp, Uint128, Uint64};
 use schemars::JsonSchema;
+use secret_toolkit::permit::Permit;
 use serde::{Deserialize, Serialize};
+
+use crate::state::{
+    AddressQuota, ChainInfo, EscrowCap, FeeSchedule, HistoryEntry, MigrationRecord,
+    PendingMigration, Quota, RelayerStats, StatsVisibility, TokenDetails, TokenRoute,
+};
+
 #[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub struct Snip20Transfer {
@@ -22,28 +29,334 @@ pub struct Snip20ReceiveMsg {
 
 #[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
-pub struct InitMsg {}
+pub struct InitMsg {
+    /// Defaults to the instantiator
+    pub admin: Option<String>,
+    /// Role that may pause the bridge, but not unpause it
+    pub pauser: Option<String>,
+    /// Mixed into the seed of the bridge's viewing keys
+    pub entropy: Option<String>,
+    /// Defaults to 256
+    pub block_size: Option<u16>,
+    /// Connections channels may be opened on. Channels can only be opened once both this and
+    /// allowed_counterparty_ports are set, here or with UpdateChannelAllowList
+    #[serde(default)]
+    pub allowed_connections: Vec<String>,
+    /// Counterparty ports channels may be opened to, e.g. "transfer"
+    #[serde(default)]
+    pub allowed_counterparty_ports: Vec<String>,
+}
 
+/// Every variant but Receive takes a padding, which is ignored. Clients fill it
+/// so the length of the encrypted message does not give away its contents.
 #[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum ExecuteMsg {
     /// This accepts a properly-encoded ReceiveMsg from a snip20 contract
     Receive(Snip20ReceiveMsg),
+    /// Register SNIP20 tokens with the bridge, or retry the registration of pending ones.
+    /// Only callable by the admin
     RegisterTokens {
         tokens: Vec<Snip20Data>,
+        padding: Option<String>,
+    },
+    /// Point a registered token at its new code hash after it was migrated. The hash is
+    /// checked with a token_info query. Only callable by the admin
+    UpdateTokenCodeHash {
+        token: String,
+        code_hash: String,
+        padding: Option<String>,
+    },
+    /// Hand the admin role to another address. Only callable by the admin
+    UpdateAdmin {
+        admin: String,
+        padding: Option<String>,
+    },
+    /// Set or remove the pauser role. Only callable by the admin
+    SetPauser {
+        pauser: Option<String>,
+        padding: Option<String>,
+    },
+    /// Pause every selected direction. Callable by the pauser or the admin
+    Pause {
+        #[serde(default)]
+        outbound: bool,
+        #[serde(default)]
+        inbound: bool,
+        #[serde(default)]
+        refunds: bool,
+        padding: Option<String>,
+    },
+    /// Lift the pause on every selected direction. Only callable by the admin
+    Unpause {
+        #[serde(default)]
+        outbound: bool,
+        #[serde(default)]
+        inbound: bool,
+        #[serde(default)]
+        refunds: bool,
+        padding: Option<String>,
+    },
+    /// Add a quota on the (channel, token) path, or replace the one with the same name.
+    /// Only callable by the admin
+    SetRateLimit {
+        channel: String,
+        token: String,
+        quota: Quota,
+        padding: Option<String>,
+    },
+    /// Only callable by the admin
+    RemoveRateLimit {
+        channel: String,
+        token: String,
+        name: String,
+        padding: Option<String>,
+    },
+    /// Clear the flows recorded against the quota. Only callable by the admin
+    ResetRateLimit {
+        channel: String,
+        token: String,
+        name: String,
+        padding: Option<String>,
+    },
+    /// Limit how much of the token each address may send out per window, or lift the limit
+    /// with None. Only callable by the admin
+    SetAddressQuota {
+        token: String,
+        quota: Option<AddressQuota>,
+        padding: Option<String>,
+    },
+    /// Allow-list an address with its own max amount per window, or None for no limit.
+    /// Only callable by the admin
+    SetQuotaOverride {
+        token: String,
+        address: String,
+        max_amount: Option<Uint128>,
+        padding: Option<String>,
+    },
+    /// Only callable by the admin
+    RemoveQuotaOverride {
+        token: String,
+        address: String,
+        padding: Option<String>,
+    },
+    /// Set the escrow caps of the token, or remove them with None. Only callable by the admin
+    SetEscrowCap {
+        token: String,
+        cap: Option<EscrowCap>,
+        padding: Option<String>,
+    },
+    /// Set a cap for the token on one channel that replaces its per channel cap, or remove it
+    /// with None. Only callable by the admin
+    SetChannelEscrowCap {
+        channel: String,
+        token: String,
+        cap: Option<Uint128>,
+        padding: Option<String>,
+    },
+    /// Set the default fee schedule and how fees are collected. Only callable by the admin
+    SetFees {
+        default: FeeSchedule,
+        collector: Option<String>,
+        accrue: bool,
+        refund_fee: bool,
+        padding: Option<String>,
+    },
+    /// Give the token its own fee schedule, or fall back to the default with None.
+    /// Only callable by the admin
+    SetTokenFee {
+        token: String,
+        schedule: Option<FeeSchedule>,
+        padding: Option<String>,
+    },
+    /// Send accrued fees of the token to the collector, all of them if amount is None.
+    /// Only callable by the fee collector
+    ClaimFees {
+        token: String,
+        amount: Option<Uint128>,
+        padding: Option<String>,
+    },
+    /// Set what relayers earn per delivered packet, which needs fees to accrue in the contract.
+    /// Only callable by the admin
+    SetRelayerIncentive {
+        fee_share_bps: u16,
+        flat: Uint128,
+        padding: Option<String>,
+    },
+    /// Send the caller's relayer rewards in the token to the caller
+    ClaimRelayerRewards {
+        token: String,
+        padding: Option<String>,
+    },
+    /// Change which connections and counterparty ports channels may be opened on.
+    /// Only callable by the admin
+    UpdateChannelAllowList {
+        #[serde(default)]
+        add_connections: Vec<String>,
+        #[serde(default)]
+        remove_connections: Vec<String>,
+        #[serde(default)]
+        add_counterparty_ports: Vec<String>,
+        #[serde(default)]
+        remove_counterparty_ports: Vec<String>,
+        padding: Option<String>,
+    },
+    /// Limit the channels the token may be sent over, or lift the limit with None.
+    /// Only callable by the admin
+    SetTokenRoute {
+        token: String,
+        route: Option<TokenRoute>,
+        padding: Option<String>,
+    },
+    /// Register a counterparty chain under its chain id, or remove it with None.
+    /// Only callable by the admin
+    SetChain {
+        chain: String,
+        info: Option<ChainInfo>,
+        padding: Option<String>,
+    },
+    /// Retire the channel and move its escrow to the successor, which then honors the
+    /// retired channel's vouchers. Both channels must reach the same chain of the registry,
+    /// so register the chain on the successor first. With a delay in seconds the migration is
+    /// only scheduled, to be completed once the delay has passed. Only callable by the admin
+    MigrateChannel {
+        channel: String,
+        successor: String,
+        delay: Option<u64>,
+        padding: Option<String>,
+    },
+    /// Complete a scheduled migration whose delay has passed. Only callable by the admin
+    CompleteChannelMigration {
+        channel: String,
+        padding: Option<String>,
+    },
+    /// Only callable by the admin
+    CancelChannelMigration {
+        channel: String,
+        padding: Option<String>,
+    },
+    /// Turn on or off rejecting outbound transfers of tokens whose escrow is short.
+    /// Only callable by the admin
+    SetEscrowGuard {
+        enabled: bool,
+        padding: Option<String>,
+    },
+    /// Send tokens the bridge holds beyond what it owes, such as ones transferred to it
+    /// without a TransferMsg. Only callable by the admin
+    Sweep {
+        token: String,
+        recipient: String,
+        amount: Uint128,
+        padding: Option<String>,
+    },
+    /// Generate a new bridge viewing key and set it on every registered token.
+    /// Only callable by the admin
+    RotateViewingKeys {
+        entropy: Option<String>,
+        padding: Option<String>,
+    },
+    /// Set the key the caller queries their history with
+    SetViewingKey {
+        key: String,
+        padding: Option<String>,
+    },
+    /// Generate a key for the caller to query their history with, returned in the data
+    CreateViewingKey {
+        entropy: String,
+        padding: Option<String>,
+    },
+    /// Stop accepting the caller's query permit with this name
+    RevokePermit {
+        permit_name: String,
+        padding: Option<String>,
+    },
+    /// Keep addresses and amounts of transfers out of event attributes, leaving the
+    /// history query as the only way to follow them. Only callable by the admin
+    SetPrivateEvents {
+        enabled: bool,
+        padding: Option<String>,
+    },
+    /// Set the block size response data and messages to tokens are padded to, 1 turns
+    /// padding off. Only callable by the admin
+    SetBlockSize {
+        block_size: u16,
+        padding: Option<String>,
+    },
+    /// Queue transfers and release them together once per epoch of this many seconds,
+    /// or send them right away with None. Only callable by the admin
+    SetBatching {
+        epoch: Option<u64>,
+        padding: Option<String>,
+    },
+    /// Release the oldest queued transfers, at most limit and 32 of them, once the epoch
+    /// has ended. The next epoch starts once the queue is empty. Callable by anyone
+    Flush {
+        limit: Option<u32>,
+        padding: Option<String>,
+    },
+    /// Take back a queued transfer of the caller that was not released yet, or whose release
+    /// and refund both failed
+    CancelTransfer { id: u64, padding: Option<String> },
+    /// Send out one queued transfer. Only callable by the contract itself while flushing
+    ReleaseTransfer { id: u64, padding: Option<String> },
+    /// Add this many decoys to the token's unlock and refund transfers, or none with None.
+    /// Only for tokens that support decoys. Only callable by the admin
+    SetTokenDecoys {
+        token: String,
+        count: Option<u8>,
+        padding: Option<String>,
+    },
+    /// Replace the addresses decoys are drawn from besides recent bridge users.
+    /// Only callable by the admin
+    SetDecoyPool {
+        addresses: Vec<String>,
+        padding: Option<String>,
+    },
+    /// Limit who sees the token's exact channel balances and statistics, or make them public
+    /// again with None. Only callable by the admin
+    SetStatsVisibility {
+        token: String,
+        visibility: Option<StatsVisibility>,
+        padding: Option<String>,
     },
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct ViewingKeyResponse {
+    pub key: String,
+}
+
 /// This is the message we accept via Receive
 #[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub struct TransferMsg {
-    /// The local channel to send the packets on
-    pub channel: String,
-    /// The remote address to send to.
+    /// The local channel to send the packets on. May be left out if chain is set
+    #[serde(default)]
+    pub channel: Option<String>,
+    /// Chain id from the registry to send to. Picks the channel if none is given
+    /// and has the remote address validated against the chain
+    #[serde(default)]
+    pub chain: Option<String>,
+    /// The remote address to send to. Validated whenever the channel leads to a registered chain
     pub remote_address: String,
     /// How long the packet lives in seconds
     pub timeout: u64,
+    /// What the packet shows the counterparty chain as the sender
+    #[serde(default)]
+    pub sender_mode: SenderMode,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Eq, PartialEq, JsonSchema, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SenderMode {
+    /// the sender's own address
+    #[default]
+    Address,
+    /// the bridge contract's address
+    Contract,
+    /// a fresh address made up for this transfer
+    Pseudonym,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
@@ -54,4 +367,270 @@ pub struct Snip20Data {
 
 #[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
-pub enum QueryMsg {}
+pub enum QueryMsg {
+    /// Returns the current Config
+    Config {},
+    /// Returns the rate limits, with their current flows, on the (channel, token) path.
+    /// Flows of private statistics are only exact for a privileged viewer
+    RateLimits {
+        channel: String,
+        token: String,
+        viewer: Option<ViewerInfo>,
+    },
+    /// Returns how much more of the token the address may send in its rolling window
+    RemainingQuota {
+        token: String,
+        address: String,
+        key: String,
+    },
+    /// Returns how much more of the token may be escrowed before hitting each cap.
+    /// Private statistics are only exact for a privileged viewer
+    EscrowHeadroom {
+        token: String,
+        viewer: Option<ViewerInfo>,
+    },
+    /// Returns the fee schedule that applies to the token
+    Fees { token: String },
+    /// Returns the fees of the token that the collector can claim. Private statistics are
+    /// only exact for a privileged viewer
+    AccruedFees {
+        token: String,
+        viewer: Option<ViewerInfo>,
+    },
+    /// Returns how many packets the relayer delivered and its unclaimed rewards
+    RelayerStats { relayer: String },
+    /// Returns the connections and counterparty ports channels may be opened on
+    ChannelAllowList {},
+    /// Returns the channels the token may use and its canonical channel per counterparty,
+    /// null if it may use any channel
+    TokenRoute { token: String },
+    /// Returns every registered counterparty chain
+    Chains {},
+    /// Returns the scheduled channel migrations and the log of executed ones. Moved amounts of
+    /// tokens with private statistics are only exact for a privileged viewer
+    ChannelMigrations { viewer: Option<ViewerInfo> },
+    /// Compares the bridge's actual balance of the token with what it owes in it.
+    /// Private statistics are only exact for a privileged viewer
+    Reconcile {
+        token: String,
+        viewer: Option<ViewerInfo>,
+    },
+    /// Returns when the current epoch ends and how many transfers are queued
+    BatchStatus {},
+    /// Returns the token's balance and total sent on the channel. Private statistics are
+    /// only exact for a privileged viewer
+    ChannelState {
+        channel: String,
+        token: String,
+        viewer: Option<ViewerInfo>,
+    },
+    /// Returns the address's transfers, refunds and receives, newest first, at most 100 per page
+    TransferHistory {
+        address: String,
+        key: String,
+        page: Option<u32>,
+        page_size: u32,
+    },
+    /// Returns every registered token with its code hash and metadata
+    ListTokens {},
+    /// Returns the SNIP-52 notification channels
+    ListChannels {},
+    /// Returns the viewer's notification seed and the state of each channel, as SNIP-52 specifies
+    ChannelInfo {
+        channels: Vec<String>,
+        viewer: ViewerInfo,
+    },
+    /// Runs the query as the signer of the permit
+    WithPermit {
+        permit: Permit,
+        query: QueryWithPermit,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum QueryWithPermit {
+    /// Needs the history permission
+    TransferHistory { page: Option<u32>, page_size: u32 },
+    /// Needs the balance permission
+    ChannelState { channel: String, token: String },
+    /// Needs the balance permission
+    RateLimits { channel: String, token: String },
+    /// Needs the balance permission
+    EscrowHeadroom { token: String },
+    /// Needs the balance permission
+    AccruedFees { token: String },
+    /// Needs the balance permission
+    Reconcile { token: String },
+    /// Needs the history permission
+    RemainingQuota { token: String },
+    /// Needs the owner permission
+    ChannelInfo { channels: Vec<String> },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct ListChannelsResponse {
+    pub channels: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct ChannelInfoResponse {
+    pub as_of_block: Uint64,
+    /// the viewer's notification seed
+    pub seed: Binary,
+    pub channels: Vec<NotificationChannelInfo>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct NotificationChannelInfo {
+    pub channel: String,
+    /// always "counter"
+    pub mode: String,
+    /// how many notifications the viewer got on the channel
+    pub counter: Uint64,
+    /// id of the next notification on the channel
+    pub next_id: Binary,
+    /// schema of the notification data
+    pub cddl: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct ViewerInfo {
+    pub address: String,
+    pub key: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct ChannelStateResponse {
+    pub outstanding: Uint128,
+    pub total_sent: Uint128,
+    /// false if the amounts were rounded down to their leading digit
+    pub exact: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct TransferHistoryResponse {
+    pub entries: Vec<HistoryEntry>,
+    pub total: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct BatchStatusResponse {
+    /// None if no epoch was started
+    pub epoch_end: Option<Timestamp>,
+    pub queued: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct ReconcileResponse {
+    /// what the bridge holds, as reported by the token
+    pub balance: Uint128,
+    /// escrow summed over all channels
+    pub outstanding: Uint128,
+    pub accrued_fees: Uint128,
+    /// unclaimed relayer rewards summed over all relayers
+    pub relayer_rewards: Uint128,
+    /// transfers waiting for the end of the epoch
+    pub queued: Uint128,
+    /// outstanding + accrued_fees + relayer_rewards + queued
+    pub liabilities: Uint128,
+    pub surplus: Uint128,
+    pub deficit: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct ChannelMigrationsResponse {
+    pub pending: Vec<PendingMigration>,
+    pub executed: Vec<MigrationRecord>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct ListTokensResponse {
+    pub tokens: Vec<RegisteredToken>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct RegisteredToken {
+    pub address: Addr,
+    pub code_hash: String,
+    /// None for tokens registered before their metadata was stored
+    pub details: Option<TokenDetails>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct ChainsResponse {
+    pub chains: Vec<RegisteredChain>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct RegisteredChain {
+    pub chain: String,
+    pub info: ChainInfo,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct ChannelAllowListResponse {
+    pub connections: Vec<String>,
+    pub counterparty_ports: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct RemainingQuotaResponse {
+    /// None if the address is not limited for this token
+    pub max_amount: Option<Uint128>,
+    pub used: Uint128,
+    /// None if the address is not limited for this token
+    pub remaining: Option<Uint128>,
+    /// when the oldest transfer in the window stops counting, None if there is none
+    pub next_release: Option<Timestamp>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct RelayerStatsResponse {
+    pub stats: RelayerStats,
+    pub rewards: Vec<RelayerReward>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct RelayerReward {
+    pub token: String,
+    pub amount: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct EscrowHeadroomResponse {
+    /// outstanding summed over all channels
+    pub outstanding: Uint128,
+    pub cap: Option<Uint128>,
+    /// None if there is no bridge wide cap
+    pub headroom: Option<Uint128>,
+    pub channels: Vec<ChannelHeadroom>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct ChannelHeadroom {
+    pub channel: String,
+    pub outstanding: Uint128,
+    pub cap: Option<Uint128>,
+    /// None if there is no cap on this channel
+    pub headroom: Option<Uint128>,
+}
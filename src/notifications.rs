@@ -0,0 +1,265 @@
+use chacha20poly1305::aead::{Aead, NewAead, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use cosmwasm_std::{
+    Addr, Api, Attribute, Binary, DepsMut, Env, StdError, StdResult, Storage, Uint128,
+};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac, NewMac};
+use minicbor::Encoder;
+use sha2::{Digest, Sha256};
+use std::convert::TryInto;
+
+use crate::msg::NotificationChannelInfo;
+use crate::state::{ReplyArgs, NOTIFICATION_COUNTERS, NOTIFICATION_SECRET};
+use crate::viewing_key::next_seed;
+
+/// The SNIP-52 channel the bridge notifies transfer outcomes on
+pub const TRANSFER_STATUS_CHANNEL: &str = "transfer_status";
+
+pub const TRANSFER_STATUS_CDDL: &str = "transfer_status = [
+  status: uint .size 1, ; 0 delivered, 1 refunded, 2 received
+  token: text,
+  amount: biguint,
+  channel: text,
+]";
+
+// plaintexts are zero padded to whole blocks, so every transfer notification looks alike
+const NOTIFICATION_BLOCK_SIZE: usize = 128;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TransferStatus {
+    /// the counterparty acknowledged the transfer
+    Delivered = 0,
+    /// the transfer failed or timed out and was refunded
+    Refunded = 1,
+    /// tokens were unlocked for the receiver of an inbound transfer
+    Received = 2,
+}
+
+/// CBOR encodes the update per TRANSFER_STATUS_CDDL
+pub fn transfer_status_data(
+    status: TransferStatus,
+    token: &str,
+    amount: Uint128,
+    channel: &str,
+) -> StdResult<Vec<u8>> {
+    let mut encoder = Encoder::new(Vec::new());
+    encoder
+        .array(4)
+        .and_then(|e| e.u8(status as u8))
+        .and_then(|e| e.str(token))
+        .and_then(|e| e.tag(minicbor::data::Tag::PosBignum))
+        .and_then(|e| e.bytes(&amount.u128().to_be_bytes()))
+        .and_then(|e| e.str(channel))
+        .map_err(|e| StdError::generic_err(e.to_string()))?;
+    Ok(encoder.into_writer())
+}
+
+/// Generated once; seeds of older deployments are set on their first notification
+pub fn init_notification_secret(
+    storage: &mut dyn Storage,
+    env: &Env,
+    entropy: &[u8],
+) -> StdResult<Vec<u8>> {
+    let seed = next_seed(storage, env, entropy)?;
+    let secret = Sha256::new()
+        .chain(&seed)
+        .chain(b"notification_secret")
+        .finalize()
+        .to_vec();
+    NOTIFICATION_SECRET.save(storage, &secret)?;
+    Ok(secret)
+}
+
+/// The seed the user's notification ids and keys are derived from, as SNIP-52 specifies
+pub fn notification_seed(storage: &dyn Storage, api: &dyn Api, user: &Addr) -> StdResult<Vec<u8>> {
+    let secret = NOTIFICATION_SECRET
+        .may_load(storage)?
+        .ok_or_else(|| StdError::generic_err("No notifications were sent yet"))?;
+    let user = api.addr_canonicalize(user.as_str())?;
+    let mut seed = vec![0u8; 32];
+    Hkdf::<Sha256>::new(None, &secret)
+        .expand(user.as_slice(), &mut seed)
+        .map_err(|_| StdError::generic_err("Invalid notification seed length"))?;
+    Ok(seed)
+}
+
+pub fn notification_id(seed: &[u8], channel: &str, counter: u64) -> StdResult<Binary> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(seed)
+        .map_err(|_| StdError::generic_err("Invalid notification seed length"))?;
+    mac.update(format!("{}:{}", channel, counter).as_bytes());
+    Ok(Binary::from(mac.finalize().into_bytes().to_vec()))
+}
+
+fn encrypt_notification(
+    seed: &[u8],
+    channel: &str,
+    counter: u64,
+    mut data: Vec<u8>,
+) -> StdResult<Vec<u8>> {
+    let surplus = data.len() % NOTIFICATION_BLOCK_SIZE;
+    if surplus != 0 {
+        data.resize(data.len() + NOTIFICATION_BLOCK_SIZE - surplus, 0);
+    }
+
+    // the channel id hash xor the zero extended counter
+    let mut nonce = [0u8; 12];
+    nonce.copy_from_slice(&Sha256::digest(channel.as_bytes())[..12]);
+    for (byte, counter_byte) in nonce[4..].iter_mut().zip(counter.to_be_bytes()) {
+        *byte ^= counter_byte;
+    }
+
+    let key: [u8; 32] = seed
+        .try_into()
+        .map_err(|_| StdError::generic_err("Invalid notification seed length"))?;
+    ChaCha20Poly1305::new(&Key::from(key))
+        .encrypt(
+            &Nonce::from(nonce),
+            Payload {
+                msg: &data,
+                aad: &[],
+            },
+        )
+        .map_err(|_| StdError::generic_err("Failed to encrypt notification"))
+}
+
+/// Counts a notification to the user on the channel and returns the plaintext attribute
+/// carrying it, keyed by its id
+pub fn notify(
+    storage: &mut dyn Storage,
+    api: &dyn Api,
+    env: &Env,
+    user: &Addr,
+    channel: &str,
+    data: Vec<u8>,
+) -> StdResult<Attribute> {
+    if NOTIFICATION_SECRET.may_load(storage)?.is_none() {
+        init_notification_secret(storage, env, user.as_bytes())?;
+    }
+    let seed = notification_seed(storage, api, user)?;
+    let counter = NOTIFICATION_COUNTERS
+        .may_load(storage, (user, channel))?
+        .unwrap_or_default()
+        + 1;
+    NOTIFICATION_COUNTERS.save(storage, (user, channel), &counter)?;
+
+    let id = notification_id(&seed, channel, counter)?;
+    let ciphertext = encrypt_notification(&seed, channel, counter, data)?;
+    Ok(Attribute {
+        key: format!("snip52:{}", id.to_base64()),
+        value: Binary::from(ciphertext).to_base64(),
+        encrypted: false,
+    })
+}
+
+/// Notifies the receiver of the reply args that their unlock or refund went through
+pub fn notify_transfer(
+    deps: DepsMut,
+    env: &Env,
+    args: &ReplyArgs,
+    status: TransferStatus,
+) -> StdResult<Attribute> {
+    notify(
+        deps.storage,
+        deps.api,
+        env,
+        &args.receiver,
+        TRANSFER_STATUS_CHANNEL,
+        transfer_status_data(status, &args.denom, args.amount, &args.channel)?,
+    )
+}
+
+/// Only call once the querier is authenticated as user
+pub fn channel_info(
+    storage: &dyn Storage,
+    user: &Addr,
+    channel: &str,
+    seed: &[u8],
+) -> StdResult<NotificationChannelInfo> {
+    if channel != TRANSFER_STATUS_CHANNEL {
+        return Err(StdError::generic_err(format!(
+            "No notification channel {}",
+            channel
+        )));
+    }
+    let counter = NOTIFICATION_COUNTERS
+        .may_load(storage, (user, channel))?
+        .unwrap_or_default();
+    Ok(NotificationChannelInfo {
+        channel: channel.to_string(),
+        mode: "counter".to_string(),
+        counter: counter.into(),
+        next_id: notification_id(seed, channel, counter + 1)?,
+        cddl: TRANSFER_STATUS_CDDL.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cosmwasm_std::testing::{mock_dependencies, mock_env};
+
+    #[test]
+    fn transfer_status_is_cbor_encoded() {
+        let data =
+            transfer_status_data(TransferStatus::Refunded, "tok", Uint128::new(0x0102), "ch")
+                .unwrap();
+
+        let mut expected = vec![0x84, 0x01, 0x63, b't', b'o', b'k', 0xc2, 0x50];
+        expected.extend_from_slice(&[0u8; 14]);
+        expected.extend_from_slice(&[0x01, 0x02, 0x62, b'c', b'h']);
+        assert_eq!(data, expected);
+    }
+
+    #[test]
+    fn notifications_decrypt_with_the_users_seed() {
+        let mut deps = mock_dependencies();
+        let env = mock_env();
+        let user = Addr::unchecked("alice");
+        let data =
+            transfer_status_data(TransferStatus::Delivered, "tok", 5u128.into(), "ch").unwrap();
+
+        let attribute = notify(
+            &mut deps.storage,
+            &deps.api,
+            &env,
+            &user,
+            TRANSFER_STATUS_CHANNEL,
+            data.clone(),
+        )
+        .unwrap();
+
+        let seed = notification_seed(&deps.storage, &deps.api, &user).unwrap();
+        let id = notification_id(&seed, TRANSFER_STATUS_CHANNEL, 1).unwrap();
+        assert_eq!(attribute.key, format!("snip52:{}", id.to_base64()));
+        assert!(!attribute.encrypted);
+
+        let ciphertext = Binary::from_base64(&attribute.value).unwrap();
+        let mut nonce = [0u8; 12];
+        nonce.copy_from_slice(&Sha256::digest(TRANSFER_STATUS_CHANNEL.as_bytes())[..12]);
+        nonce[11] ^= 1;
+        let plaintext = ChaCha20Poly1305::new(Key::from_slice(&seed))
+            .decrypt(Nonce::from_slice(&nonce), ciphertext.as_slice())
+            .unwrap();
+        assert_eq!(plaintext.len(), NOTIFICATION_BLOCK_SIZE);
+        assert_eq!(&plaintext[..data.len()], data.as_slice());
+        assert!(plaintext[data.len()..].iter().all(|byte| *byte == 0));
+
+        // the next notification is counted on
+        let info = channel_info(&deps.storage, &user, TRANSFER_STATUS_CHANNEL, &seed).unwrap();
+        assert_eq!(info.counter, 1u64.into());
+        assert_eq!(
+            info.next_id,
+            notification_id(&seed, TRANSFER_STATUS_CHANNEL, 2).unwrap()
+        );
+    }
+
+    #[test]
+    fn seeds_and_channels_must_exist() {
+        let deps = mock_dependencies();
+        let user = Addr::unchecked("alice");
+        notification_seed(&deps.storage, &deps.api, &user).unwrap_err();
+        channel_info(&deps.storage, &user, "other", &[0u8; 32]).unwrap_err();
+        encrypt_notification(&[0u8; 16], TRANSFER_STATUS_CHANNEL, 1, vec![]).unwrap_err();
+    }
+}
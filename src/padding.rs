@@ -0,0 +1,85 @@
+use cosmwasm_std::Binary;
+use secret_toolkit::utils::space_pad;
+
+pub const DEFAULT_BLOCK_SIZE: u16 = 256;
+
+/// Pads a message we send to a whole number of blocks, hiding its exact length.
+/// JSON parsers ignore the trailing spaces.
+pub fn pad_binary(mut binary: Binary, block_size: u16) -> Binary {
+    space_pad(&mut binary.0, block_size.into());
+    binary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
+    use cosmwasm_std::{CosmosMsg, Uint128, WasmMsg};
+
+    use crate::contract::{execute, query};
+    use crate::error::ContractError;
+    use crate::msg::{ExecuteMsg, QueryMsg};
+    use crate::state::increase_channel_balance;
+    use crate::test_helpers::{add_channel, add_token, mock_token, setup, ADMIN};
+
+    fn set_block_size(block_size: u16) -> ExecuteMsg {
+        ExecuteMsg::SetBlockSize {
+            block_size,
+            padding: None,
+        }
+    }
+
+    #[test]
+    fn binaries_are_padded_to_whole_blocks() {
+        let padded = pad_binary(Binary::from(b"{}".to_vec()), 16);
+        assert_eq!(padded.as_slice(), b"{}              ");
+        let full = pad_binary(Binary::from([b'a'; 16].to_vec()), 16);
+        assert_eq!(full.len(), 16);
+    }
+
+    #[test]
+    fn responses_queries_and_token_messages_are_padded() {
+        let mut deps = mock_dependencies();
+        setup(deps.as_mut());
+        add_channel(&mut deps.storage, "channel-0", "connection-0");
+        add_token(&mut deps.storage, "token");
+        increase_channel_balance(&mut deps.storage, "channel-0", "token", Uint128::new(100))
+            .unwrap();
+        mock_token(&mut deps.querier, 150);
+
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADMIN, &[]),
+            set_block_size(0),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::InvalidBlockSize {});
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADMIN, &[]),
+            set_block_size(64),
+        )
+        .unwrap();
+
+        let config = query(deps.as_ref(), mock_env(), QueryMsg::Config {}).unwrap();
+        assert_eq!(config.len() % 64, 0);
+
+        let sweep = ExecuteMsg::Sweep {
+            token: "token".into(),
+            recipient: "treasury".into(),
+            amount: Uint128::new(50),
+            padding: None,
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info(ADMIN, &[]), sweep).unwrap();
+        match &res.messages[0].msg {
+            CosmosMsg::Wasm(WasmMsg::Execute { msg, .. }) => assert_eq!(msg.len() % 64, 0),
+            other => panic!("not a token message: {:?}", other),
+        }
+        for attribute in &res.attributes {
+            assert_eq!(attribute.key.len() % 64, 0);
+            assert_eq!(attribute.value.len() % 64, 0);
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use cosmwasm_std::{Addr, Deps, Env, StdError, StdResult, Storage};
+use secret_toolkit::permit::{validate, Permit, RevokedPermits, TokenPermissions};
+
+use crate::registry::address_prefix;
+
+/// storage prefix of the permit names their signers revoked
+const PREFIX_REVOKED_PERMITS: &str = "revoked_permits";
+
+/// Checks the SNIP-24 permit was signed for this contract on this chain, grants the permission
+/// and has not been revoked. Returns the address of the signer.
+pub fn validate_permit(
+    deps: Deps,
+    env: &Env,
+    permit: &Permit,
+    permission: TokenPermissions,
+) -> StdResult<Addr> {
+    // the signature covers the chain id, but only we can tell it is this chain's
+    if permit.params.chain_id != env.block.chain_id {
+        return Err(StdError::generic_err("Permit is for another chain"));
+    }
+    if !permit.check_permission(&permission) {
+        return Err(StdError::generic_err(
+            "Permit does not grant this permission",
+        ));
+    }
+
+    // signers share the prefix of the chain's addresses, like this contract's
+    let contract = env.contract.address.as_str();
+    let signer = validate(
+        deps,
+        PREFIX_REVOKED_PERMITS,
+        permit,
+        contract.to_string(),
+        Some(address_prefix(contract)),
+    )?;
+    deps.api.addr_validate(&signer)
+}
+
+pub fn revoke_permit(storage: &mut dyn Storage, signer: &Addr, permit_name: &str) {
+    RevokedPermits::revoke_permit(
+        storage,
+        PREFIX_REVOKED_PERMITS,
+        signer.as_str(),
+        permit_name,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cosmwasm_std::testing::{mock_dependencies, mock_env};
+    use cosmwasm_std::{from_slice, Binary};
+    use secret_toolkit::permit::pubkey_to_account;
+
+    const CONTRACT: &str = "secret1bridge";
+
+    fn env() -> Env {
+        let mut env = mock_env();
+        env.contract.address = Addr::unchecked(CONTRACT);
+        env
+    }
+
+    fn permit(chain_id: &str, token: &str, permission: &str) -> Permit {
+        from_slice(
+            format!(
+                r#"{{
+                    "params": {{
+                        "allowed_tokens": ["{}"],
+                        "permit_name": "bridge",
+                        "chain_id": "{}",
+                        "permissions": ["{}"]
+                    }},
+                    "signature": {{
+                        "pub_key": {{
+                            "type": "tendermint/PubKeySecp256k1",
+                            "value": "{}"
+                        }},
+                        "signature": "{}"
+                    }}
+                }}"#,
+                token,
+                chain_id,
+                permission,
+                Binary::from([2u8; 33]).to_base64(),
+                Binary::from([0u8; 64]).to_base64(),
+            )
+            .as_bytes(),
+        )
+        .unwrap()
+    }
+
+    fn check(deps: Deps, permit: &Permit) -> String {
+        validate_permit(deps, &env(), permit, TokenPermissions::Balance)
+            .unwrap_err()
+            .to_string()
+    }
+
+    #[test]
+    fn permits_of_other_chains_and_contracts_are_rejected() {
+        let deps = mock_dependencies();
+        let chain_id = env().block.chain_id;
+
+        let err = check(deps.as_ref(), &permit("other-chain", CONTRACT, "balance"));
+        assert!(err.contains("another chain"), "{}", err);
+
+        let err = check(deps.as_ref(), &permit(&chain_id, "secret1other", "balance"));
+        assert!(err.contains("doesn't apply"), "{}", err);
+    }
+
+    #[test]
+    fn permits_need_the_permission() {
+        let deps = mock_dependencies();
+        let chain_id = env().block.chain_id;
+
+        let err = check(deps.as_ref(), &permit(&chain_id, CONTRACT, "history"));
+        assert!(err.contains("does not grant"), "{}", err);
+    }
+
+    #[test]
+    fn revoked_permits_are_rejected() {
+        let mut deps = mock_dependencies();
+        let permit = permit(&env().block.chain_id, CONTRACT, "balance");
+
+        let account = pubkey_to_account(&permit.signature.pub_key.value);
+        let signer = bech32::encode(
+            "secret",
+            bech32::ToBase32::to_base32(&account.as_slice()),
+            bech32::Variant::Bech32,
+        )
+        .unwrap();
+        revoke_permit(&mut deps.storage, &Addr::unchecked(signer), "bridge");
+
+        let err = check(deps.as_ref(), &permit);
+        assert!(err.contains("revoked"), "{}", err);
+    }
+}
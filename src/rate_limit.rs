@@ -0,0 +1,502 @@
+use cosmwasm_std::{StdResult, Storage, Timestamp, Uint128};
+
+use crate::error::ContractError;
+use crate::state::{
+    Flow, FlowBucket, Quota, RateLimit, ADDRESS_QUOTAS, ADDRESS_QUOTA_OVERRIDES, ADDRESS_USAGE,
+    CHANNEL_STATE, RATE_LIMITS,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FlowType {
+    /// escrowed tokens unlocked by an incoming packet
+    In,
+    /// tokens escrowed by an outgoing transfer
+    Out,
+}
+
+impl Quota {
+    pub fn validate(&self) -> Result<(), ContractError> {
+        let reason = if self.name.is_empty() {
+            "name must not be empty"
+        } else if self.duration == 0 {
+            "duration must be positive"
+        } else if self.max_percentage_send.unwrap_or_default() > 100
+            || self.max_percentage_recv.unwrap_or_default() > 100
+        {
+            "percentages cannot exceed 100"
+        } else if self.max_percentage_send.is_none()
+            && self.max_percentage_recv.is_none()
+            && self.max_amount_send.is_none()
+            && self.max_amount_recv.is_none()
+        {
+            "at least one limit must be set"
+        } else {
+            return Ok(());
+        };
+        Err(ContractError::InvalidQuota {
+            name: self.name.clone(),
+            reason: reason.into(),
+        })
+    }
+
+    // the amount allowed to move in the given direction during one window, if limited.
+    // A percentage of an empty escrow allows no inflow and does not limit outflow.
+    fn capacity(&self, direction: FlowType, escrow: Uint128) -> Option<Uint128> {
+        let (percentage, amount) = match direction {
+            FlowType::In => (self.max_percentage_recv, self.max_amount_recv),
+            FlowType::Out => (self.max_percentage_send, self.max_amount_send),
+        };
+        let from_percentage = percentage
+            .filter(|_| direction == FlowType::In || !escrow.is_zero())
+            .map(|percentage| escrow.multiply_ratio(percentage, 100u32));
+        match (from_percentage, amount) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+}
+
+/// Windows are split into this many buckets, so flows leave them in steps of a bucket
+const WINDOW_BUCKETS: u64 = 24;
+
+impl FlowBucket {
+    fn get(&self, direction: FlowType) -> Uint128 {
+        match direction {
+            FlowType::In => self.inflow,
+            FlowType::Out => self.outflow,
+        }
+    }
+
+    fn get_mut(&mut self, direction: FlowType) -> &mut Uint128 {
+        match direction {
+            FlowType::In => &mut self.inflow,
+            FlowType::Out => &mut self.outflow,
+        }
+    }
+}
+
+impl Flow {
+    /// Drops the buckets that fell out of the window ending now
+    pub fn expire(&mut self, now: Timestamp, duration: u64) {
+        self.buckets
+            .retain(|bucket| bucket.start.plus_seconds(duration) > now);
+    }
+
+    /// Adds the amount to the current bucket, opening it if needed
+    pub fn record(&mut self, now: Timestamp, duration: u64, amount: Uint128, direction: FlowType) {
+        let width = (duration / WINDOW_BUCKETS).max(1);
+        let start = Timestamp::from_seconds(now.seconds() - now.seconds() % width);
+        match self.buckets.last_mut() {
+            Some(bucket) if bucket.start == start => *bucket.get_mut(direction) += amount,
+            _ => {
+                let mut bucket = FlowBucket {
+                    start,
+                    inflow: Uint128::zero(),
+                    outflow: Uint128::zero(),
+                };
+                *bucket.get_mut(direction) = amount;
+                self.buckets.push(bucket);
+            }
+        }
+    }
+
+    /// Takes the amount back from the newest buckets
+    pub fn undo(&mut self, mut amount: Uint128, direction: FlowType) {
+        for bucket in self.buckets.iter_mut().rev() {
+            let flow = bucket.get_mut(direction);
+            let taken = amount.min(*flow);
+            *flow -= taken;
+            amount -= taken;
+        }
+    }
+
+    /// Everything that moved in the direction during the window
+    pub fn total(&self, direction: FlowType) -> Uint128 {
+        self.buckets
+            .iter()
+            .map(|bucket| bucket.get(direction))
+            .sum()
+    }
+
+    /// When the oldest flow stops counting, None if the window is empty
+    pub fn next_release(&self, duration: u64) -> Option<Timestamp> {
+        self.buckets
+            .first()
+            .map(|bucket| bucket.start.plus_seconds(duration))
+    }
+}
+
+impl RateLimit {
+    pub fn new(quota: Quota) -> Self {
+        RateLimit {
+            quota,
+            flow: Flow::default(),
+        }
+    }
+}
+
+/// Records a flow on the (channel, denom) path, failing if any quota on it would be exceeded.
+/// Quotas apply to the sum over a rolling window and are checked before the escrow moves.
+pub fn check_and_update_flow(
+    storage: &mut dyn Storage,
+    now: Timestamp,
+    channel: &str,
+    denom: &str,
+    amount: Uint128,
+    direction: FlowType,
+) -> Result<(), ContractError> {
+    let mut limits = match RATE_LIMITS.may_load(storage, (channel, denom))? {
+        Some(limits) => limits,
+        None => return Ok(()),
+    };
+    let escrow = CHANNEL_STATE
+        .may_load(storage, (channel, denom))?
+        .unwrap_or_default()
+        .outstanding;
+
+    for limit in limits.iter_mut() {
+        let duration = limit.quota.duration;
+        limit.flow.expire(now, duration);
+        limit.flow.record(now, duration, amount, direction);
+        if let Some(capacity) = limit.quota.capacity(direction, escrow) {
+            if limit.flow.total(direction) > capacity {
+                return Err(ContractError::RateLimitExceeded {
+                    channel: channel.into(),
+                    denom: denom.into(),
+                    quota: limit.quota.name.clone(),
+                    reset: limit.flow.next_release(duration).unwrap_or(now),
+                });
+            }
+        }
+    }
+
+    RATE_LIMITS.save(storage, (channel, denom), &limits)?;
+    Ok(())
+}
+
+/// Takes back a flow that did not happen after all (failed sends, failed unlocks).
+/// Whatever of it already left the window is left alone.
+pub fn undo_flow(
+    storage: &mut dyn Storage,
+    now: Timestamp,
+    channel: &str,
+    denom: &str,
+    amount: Uint128,
+    direction: FlowType,
+) -> StdResult<()> {
+    let mut limits = match RATE_LIMITS.may_load(storage, (channel, denom))? {
+        Some(limits) => limits,
+        None => return Ok(()),
+    };
+
+    for limit in limits.iter_mut() {
+        limit.flow.expire(now, limit.quota.duration);
+        limit.flow.undo(amount, direction);
+    }
+
+    RATE_LIMITS.save(storage, (channel, denom), &limits)
+}
+
+/// The limit and window that apply to `address` sending `token`, None if it may send freely
+fn address_limit(
+    storage: &dyn Storage,
+    token: &str,
+    address: &str,
+) -> StdResult<Option<(Uint128, u64)>> {
+    let quota = match ADDRESS_QUOTAS.may_load(storage, token)? {
+        Some(quota) => quota,
+        None => return Ok(None),
+    };
+    let max_amount = match ADDRESS_QUOTA_OVERRIDES.may_load(storage, (token, address))? {
+        Some(over) => over.max_amount,
+        None => Some(quota.max_amount),
+    };
+    Ok(max_amount.map(|max_amount| (max_amount, quota.window)))
+}
+
+// what the address sent during the window ending now
+fn current_usage(
+    storage: &dyn Storage,
+    now: Timestamp,
+    token: &str,
+    address: &str,
+    window: u64,
+) -> StdResult<Flow> {
+    let mut usage = ADDRESS_USAGE
+        .may_load(storage, (token, address))?
+        .unwrap_or_default();
+    usage.expire(now, window);
+    Ok(usage)
+}
+
+/// Records `amount` sent by `address`, failing if it would exceed the address quota of the
+/// token over its rolling window
+pub fn check_and_update_address_quota(
+    storage: &mut dyn Storage,
+    now: Timestamp,
+    token: &str,
+    address: &str,
+    amount: Uint128,
+) -> Result<(), ContractError> {
+    let (max_amount, window) = match address_limit(storage, token, address)? {
+        Some(limit) => limit,
+        None => return Ok(()),
+    };
+
+    let mut usage = current_usage(storage, now, token, address, window)?;
+    let used = usage.total(FlowType::Out);
+    if used + amount > max_amount {
+        return Err(ContractError::AddressQuotaExceeded {
+            address: address.into(),
+            token: token.into(),
+            remaining: max_amount.saturating_sub(used),
+        });
+    }
+    usage.record(now, window, amount, FlowType::Out);
+
+    ADDRESS_USAGE.save(storage, (token, address), &usage)?;
+    Ok(())
+}
+
+/// Gives back quota for a transfer that was refunded, as far as it is still in the window
+pub fn undo_address_usage(
+    storage: &mut dyn Storage,
+    now: Timestamp,
+    token: &str,
+    address: &str,
+    amount: Uint128,
+) -> StdResult<()> {
+    let window = match ADDRESS_QUOTAS.may_load(storage, token)? {
+        Some(quota) => quota.window,
+        None => return Ok(()),
+    };
+    let mut usage = current_usage(storage, now, token, address, window)?;
+    usage.undo(amount, FlowType::Out);
+    ADDRESS_USAGE.save(storage, (token, address), &usage)
+}
+
+/// Returns (max amount, used, when the oldest transfer stops counting) for the address,
+/// None if it is not limited
+pub fn address_quota_status(
+    storage: &dyn Storage,
+    now: Timestamp,
+    token: &str,
+    address: &str,
+) -> StdResult<Option<(Uint128, Uint128, Option<Timestamp>)>> {
+    let (max_amount, window) = match address_limit(storage, token, address)? {
+        Some(limit) => limit,
+        None => return Ok(None),
+    };
+    let usage = current_usage(storage, now, token, address, window)?;
+    Ok(Some((
+        max_amount,
+        usage.total(FlowType::Out),
+        usage.next_release(window),
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cosmwasm_std::testing::MockStorage;
+
+    use crate::state::ChannelState;
+
+    const DAY: u64 = 86400;
+
+    fn quota(max_amount_send: u128) -> Quota {
+        Quota {
+            name: "daily".into(),
+            duration: DAY,
+            max_percentage_send: None,
+            max_percentage_recv: None,
+            max_amount_send: Some(Uint128::new(max_amount_send)),
+            max_amount_recv: None,
+        }
+    }
+
+    fn send(storage: &mut dyn Storage, now: u64, amount: u128) -> Result<(), ContractError> {
+        check_and_update_flow(
+            storage,
+            Timestamp::from_seconds(now),
+            "channel-0",
+            "token",
+            Uint128::new(amount),
+            FlowType::Out,
+        )
+    }
+
+    #[test]
+    fn quota_validation() {
+        quota(100).validate().unwrap();
+
+        let mut empty = quota(100);
+        empty.max_amount_send = None;
+        assert_eq!(
+            empty.validate().unwrap_err(),
+            ContractError::InvalidQuota {
+                name: "daily".into(),
+                reason: "at least one limit must be set".into(),
+            }
+        );
+
+        let mut over = quota(100);
+        over.max_percentage_recv = Some(101);
+        assert!(over.validate().is_err());
+    }
+
+    #[test]
+    fn flows_leave_the_rolling_window() {
+        let mut storage = MockStorage::new();
+        RATE_LIMITS
+            .save(
+                &mut storage,
+                ("channel-0", "token"),
+                &vec![RateLimit::new(quota(100))],
+            )
+            .unwrap();
+
+        send(&mut storage, 1_000_000, 60).unwrap();
+        send(&mut storage, 1_000_000 + DAY / 2, 40).unwrap();
+        let err = send(&mut storage, 1_000_000 + DAY / 2, 1).unwrap_err();
+        assert!(matches!(err, ContractError::RateLimitExceeded { .. }));
+
+        // the first transfer left the window, the second still counts
+        send(&mut storage, 1_000_000 + DAY + DAY / 24, 60).unwrap();
+        let err = send(&mut storage, 1_000_000 + DAY + DAY / 24, 1).unwrap_err();
+        assert!(matches!(err, ContractError::RateLimitExceeded { .. }));
+    }
+
+    #[test]
+    fn rejected_flows_are_not_recorded() {
+        let mut storage = MockStorage::new();
+        RATE_LIMITS
+            .save(
+                &mut storage,
+                ("channel-0", "token"),
+                &vec![RateLimit::new(quota(100))],
+            )
+            .unwrap();
+
+        send(&mut storage, 1_000_000, 90).unwrap();
+        send(&mut storage, 1_000_000, 20).unwrap_err();
+        send(&mut storage, 1_000_000, 10).unwrap();
+    }
+
+    #[test]
+    fn undone_flows_free_the_quota() {
+        let mut storage = MockStorage::new();
+        RATE_LIMITS
+            .save(
+                &mut storage,
+                ("channel-0", "token"),
+                &vec![RateLimit::new(quota(100))],
+            )
+            .unwrap();
+
+        send(&mut storage, 1_000_000, 100).unwrap();
+        undo_flow(
+            &mut storage,
+            Timestamp::from_seconds(1_000_000),
+            "channel-0",
+            "token",
+            Uint128::new(30),
+            FlowType::Out,
+        )
+        .unwrap();
+        send(&mut storage, 1_000_000, 30).unwrap();
+        send(&mut storage, 1_000_000, 1).unwrap_err();
+    }
+
+    #[test]
+    fn percentages_are_of_the_escrow_before_the_transfer() {
+        let mut storage = MockStorage::new();
+        let mut percentage = quota(0);
+        percentage.max_amount_send = None;
+        percentage.max_percentage_send = Some(10);
+        percentage.max_percentage_recv = Some(10);
+        RATE_LIMITS
+            .save(
+                &mut storage,
+                ("channel-0", "token"),
+                &vec![RateLimit::new(percentage)],
+            )
+            .unwrap();
+
+        // nothing escrowed: outflow is free, inflow is not
+        send(&mut storage, 1_000_000, 500).unwrap();
+        let err = check_and_update_flow(
+            &mut storage,
+            Timestamp::from_seconds(1_000_000),
+            "channel-0",
+            "token",
+            Uint128::new(1),
+            FlowType::In,
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::RateLimitExceeded { .. }));
+
+        CHANNEL_STATE
+            .save(
+                &mut storage,
+                ("channel-0", "token"),
+                &ChannelState {
+                    outstanding: Uint128::new(1000),
+                    total_sent: Uint128::new(1000),
+                },
+            )
+            .unwrap();
+        check_and_update_flow(
+            &mut storage,
+            Timestamp::from_seconds(1_000_000),
+            "channel-0",
+            "token",
+            Uint128::new(100),
+            FlowType::In,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn address_quotas_roll_and_report_their_release() {
+        let mut storage = MockStorage::new();
+        ADDRESS_QUOTAS
+            .save(
+                &mut storage,
+                "token",
+                &crate::state::AddressQuota {
+                    max_amount: Uint128::new(100),
+                    window: DAY,
+                },
+            )
+            .unwrap();
+        let now = Timestamp::from_seconds(1_000_000);
+
+        check_and_update_address_quota(&mut storage, now, "token", "alice", Uint128::new(80))
+            .unwrap();
+        let err =
+            check_and_update_address_quota(&mut storage, now, "token", "alice", Uint128::new(30))
+                .unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::AddressQuotaExceeded {
+                address: "alice".into(),
+                token: "token".into(),
+                remaining: Uint128::new(20),
+            }
+        );
+        // others have their own quota
+        check_and_update_address_quota(&mut storage, now, "token", "bob", Uint128::new(100))
+            .unwrap();
+
+        let (max, used, release) = address_quota_status(&storage, now, "token", "alice")
+            .unwrap()
+            .unwrap();
+        assert_eq!((max, used), (Uint128::new(100), Uint128::new(80)));
+        let release = release.unwrap();
+        assert!(release > now && release <= now.plus_seconds(DAY));
+
+        check_and_update_address_quota(&mut storage, release, "token", "alice", Uint128::new(100))
+            .unwrap();
+    }
+}
@@ -0,0 +1,139 @@
+use cosmwasm_std::{Deps, Env, Order, StdError, StdResult, Storage, Uint128};
+
+use crate::msg::ReconcileResponse;
+use crate::state::{
+    total_outstanding, ACCRUED_FEES, CODE_HASH, FAILED_RELEASES, QUEUED_TRANSFERS, RELAYER_REWARDS,
+};
+use crate::tokens::{Snip20QueryAnswer, Snip20QueryMsg};
+use crate::viewing_key::bridge_viewing_key;
+
+/// The bridge's own balance of the token, read with its viewing key
+pub fn snip20_balance(deps: Deps, env: &Env, token: &str) -> StdResult<Uint128> {
+    let code_hash = CODE_HASH.load(deps.storage, deps.api.addr_validate(token)?)?;
+    let answer: Snip20QueryAnswer = deps.querier.query_wasm_smart(
+        code_hash,
+        token,
+        &Snip20QueryMsg::Balance {
+            address: env.contract.address.to_string(),
+            key: bridge_viewing_key(deps.storage)?,
+        },
+    )?;
+    match answer {
+        Snip20QueryAnswer::Balance { amount } => Ok(amount),
+        _ => Err(StdError::generic_err("Unexpected answer to balance")),
+    }
+}
+
+/// Unclaimed relayer rewards in the token, summed over all relayers
+fn unclaimed_rewards(storage: &dyn Storage, token: &str) -> StdResult<Uint128> {
+    RELAYER_REWARDS
+        .range(storage, None, None, Order::Ascending)
+        .try_fold(Uint128::zero(), |total, entry| {
+            let ((_, reward_token), amount) = entry?;
+            Ok(if reward_token == token {
+                total + amount
+            } else {
+                total
+            })
+        })
+}
+
+/// Compares what the bridge holds of the token with everything it owes in it:
+/// the escrow of all channels, fees waiting for the collector and unclaimed relayer rewards
+pub fn reconcile(deps: Deps, env: &Env, token: &str) -> StdResult<ReconcileResponse> {
+    let balance = snip20_balance(deps, env, token)?;
+    let outstanding = total_outstanding(deps.storage, token)?;
+    let accrued_fees = ACCRUED_FEES
+        .may_load(deps.storage, token)?
+        .unwrap_or_default();
+    let relayer_rewards = unclaimed_rewards(deps.storage, token)?;
+    // transfers whose release failed are owed back to their senders until refunded
+    let queued = QUEUED_TRANSFERS
+        .range(deps.storage, None, None, Order::Ascending)
+        .chain(FAILED_RELEASES.range(deps.storage, None, None, Order::Ascending))
+        .try_fold(Uint128::zero(), |total, entry| {
+            let (_, queued) = entry?;
+            Ok::<_, StdError>(if queued.token == token {
+                total + queued.amount
+            } else {
+                total
+            })
+        })?;
+    let liabilities = outstanding + accrued_fees + relayer_rewards + queued;
+
+    Ok(ReconcileResponse {
+        balance,
+        outstanding,
+        accrued_fees,
+        relayer_rewards,
+        queued,
+        liabilities,
+        surplus: balance.saturating_sub(liabilities),
+        deficit: liabilities.saturating_sub(balance),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cosmwasm_std::testing::{mock_dependencies, mock_env};
+    use cosmwasm_std::Addr;
+
+    use crate::fees::accrue_fee;
+    use crate::state::{increase_channel_balance, QueuedTransfer};
+    use crate::test_helpers::{add_channel, add_token, mock_token, setup, transfer_msg};
+
+    #[test]
+    fn every_liability_counts_against_the_balance() {
+        let mut deps = mock_dependencies();
+        setup(deps.as_mut());
+        add_channel(&mut deps.storage, "channel-0", "connection-0");
+        add_channel(&mut deps.storage, "channel-1", "connection-0");
+        add_token(&mut deps.storage, "token");
+        increase_channel_balance(&mut deps.storage, "channel-0", "token", Uint128::new(60))
+            .unwrap();
+        increase_channel_balance(&mut deps.storage, "channel-1", "token", Uint128::new(40))
+            .unwrap();
+        accrue_fee(&mut deps.storage, "token", Uint128::new(5)).unwrap();
+        RELAYER_REWARDS
+            .save(
+                &mut deps.storage,
+                (&Addr::unchecked("relayer"), "token"),
+                &Uint128::new(3),
+            )
+            .unwrap();
+        let queued = QueuedTransfer {
+            msg: transfer_msg("channel-0", "remote"),
+            token: Addr::unchecked("token"),
+            amount: Uint128::new(2),
+            sender: Addr::unchecked("alice"),
+        };
+        QUEUED_TRANSFERS
+            .save(&mut deps.storage, 0, &queued)
+            .unwrap();
+        // other tokens don't count
+        increase_channel_balance(&mut deps.storage, "channel-0", "other", Uint128::new(1000))
+            .unwrap();
+
+        mock_token(&mut deps.querier, 120);
+        let status = reconcile(deps.as_ref(), &mock_env(), "token").unwrap();
+        assert_eq!(
+            status,
+            ReconcileResponse {
+                balance: Uint128::new(120),
+                outstanding: Uint128::new(100),
+                accrued_fees: Uint128::new(5),
+                relayer_rewards: Uint128::new(3),
+                queued: Uint128::new(2),
+                liabilities: Uint128::new(110),
+                surplus: Uint128::new(10),
+                deficit: Uint128::zero(),
+            }
+        );
+
+        mock_token(&mut deps.querier, 100);
+        let status = reconcile(deps.as_ref(), &mock_env(), "token").unwrap();
+        assert_eq!(status.surplus, Uint128::zero());
+        assert_eq!(status.deficit, Uint128::new(10));
+    }
+}
@@ -0,0 +1,259 @@
+use bech32::{FromBase32, Variant};
+use cosmwasm_std::{Order, StdResult, Storage};
+
+use crate::error::ContractError;
+use crate::state::{
+    ChainInfo, TokenRoute, CHAIN_REGISTRY, CHANNEL_CHAINS, CHANNEL_INFO, TOKEN_ROUTES,
+};
+
+impl ChainInfo {
+    pub fn validate(&self, storage: &dyn Storage) -> Result<(), ContractError> {
+        if !CHANNEL_INFO.has(storage, &self.channel) {
+            return Err(ContractError::NoSuchChannel {
+                id: self.channel.clone(),
+            });
+        }
+        if self.bech32_prefix.is_empty() || self.bech32_prefix != self.bech32_prefix.to_lowercase()
+        {
+            return Err(ContractError::InvalidChainInfo {
+                reason: "bech32_prefix must be a non-empty lowercase string".into(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Decodes the address and checks it carries this chain's prefix and an allowed length
+    pub fn validate_address(&self, address: &str) -> Result<(), ContractError> {
+        let invalid = |reason: String| ContractError::InvalidRemoteAddress {
+            address: address.to_string(),
+            reason,
+        };
+        let (prefix, data, variant) =
+            bech32::decode(address).map_err(|e| invalid(e.to_string()))?;
+        if prefix != self.bech32_prefix {
+            return Err(invalid(format!("expected prefix {}", self.bech32_prefix)));
+        }
+        // account addresses use the original checksum, never bech32m
+        if variant != Variant::Bech32 {
+            return Err(invalid("expected a bech32 checksum".into()));
+        }
+        let bytes = Vec::<u8>::from_base32(&data).map_err(|e| invalid(e.to_string()))?;
+        if !self.address_lengths.is_empty()
+            && !self
+                .address_lengths
+                .iter()
+                .any(|l| *l as usize == bytes.len())
+        {
+            return Err(invalid(format!("unexpected length {}", bytes.len())));
+        }
+        Ok(())
+    }
+}
+
+impl TokenRoute {
+    /// Every channel must exist, and every canonical channel must be for a distinct
+    /// registered chain
+    pub fn validate(&self, storage: &dyn Storage) -> Result<(), ContractError> {
+        let channels = self
+            .allowed_channels
+            .iter()
+            .chain(self.canonical_channels.iter().map(|c| &c.channel));
+        for channel in channels {
+            if !CHANNEL_INFO.has(storage, channel) {
+                return Err(ContractError::NoSuchChannel {
+                    id: channel.clone(),
+                });
+            }
+        }
+        for (i, canonical) in self.canonical_channels.iter().enumerate() {
+            if !CHAIN_REGISTRY.has(storage, &canonical.chain) {
+                return Err(ContractError::UnknownChain {
+                    chain: canonical.chain.clone(),
+                });
+            }
+            if self.canonical_channels[..i]
+                .iter()
+                .any(|other| other.chain == canonical.chain)
+            {
+                return Err(ContractError::InvalidTokenRoute {
+                    reason: format!(
+                        "chain {} has more than one canonical channel",
+                        canonical.chain
+                    ),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Picks the channel a transfer goes over, along with the registered chain on the other end
+/// if there is one, so the remote address can be checked against it.
+///
+/// A chain goes to the token's canonical channel for it, or else the chain's registered channel.
+/// An explicit channel must be one of those two when a chain is given as well.
+pub fn resolve_target(
+    storage: &dyn Storage,
+    token: &str,
+    channel: Option<String>,
+    chain: Option<String>,
+) -> Result<(String, Option<ChainInfo>), ContractError> {
+    match (channel, chain) {
+        (channel, Some(chain)) => {
+            let info =
+                CHAIN_REGISTRY
+                    .may_load(storage, &chain)?
+                    .ok_or(ContractError::UnknownChain {
+                        chain: chain.clone(),
+                    })?;
+            let canonical = TOKEN_ROUTES
+                .may_load(storage, token)?
+                .and_then(|route| route.canonical_channel(&chain).map(String::from));
+            let channel = match channel {
+                Some(channel)
+                    if channel == info.channel || canonical.as_ref() == Some(&channel) =>
+                {
+                    channel
+                }
+                Some(channel) => return Err(ContractError::ChannelNotOnChain { channel, chain }),
+                None => canonical.unwrap_or_else(|| info.channel.clone()),
+            };
+            Ok((channel, Some(info)))
+        }
+        (Some(channel), None) => {
+            let info = chain_on_channel(storage, token, &channel)?;
+            Ok((channel, info))
+        }
+        (None, None) => Err(ContractError::NoTransferTarget {}),
+    }
+}
+
+/// The id of the registered chain the channel reaches, independent of any token: the chain it
+/// is or was registered for, else the chain whose registered channel shares its connection
+pub fn registered_chain_id(storage: &dyn Storage, channel: &str) -> StdResult<Option<String>> {
+    if let Some(chain) = CHANNEL_CHAINS.may_load(storage, channel)? {
+        if CHAIN_REGISTRY.has(storage, &chain) {
+            return Ok(Some(chain));
+        }
+    }
+    let connection = CHANNEL_INFO
+        .may_load(storage, channel)?
+        .map(|info| info.connection_id);
+    let mut on_connection = None;
+    for entry in CHAIN_REGISTRY.range(storage, None, None, Order::Ascending) {
+        let (chain, info) = entry?;
+        if info.channel == channel {
+            return Ok(Some(chain));
+        }
+        if on_connection.is_none() && connection.is_some() {
+            let registered = CHANNEL_INFO.may_load(storage, &info.channel)?;
+            if registered.map(|r| r.connection_id) == connection {
+                on_connection = Some(chain);
+            }
+        }
+    }
+    Ok(on_connection)
+}
+
+/// The human readable part of a bech32 address
+pub fn address_prefix(address: &str) -> &str {
+    address.rfind('1').map_or("", |i| &address[..i])
+}
+
+/// The registered chain at the other end of the channel: the chain the token's canonical
+/// channel goes to, else the chain the channel reaches at all
+fn chain_on_channel(
+    storage: &dyn Storage,
+    token: &str,
+    channel: &str,
+) -> StdResult<Option<ChainInfo>> {
+    let canonical_for = TOKEN_ROUTES.may_load(storage, token)?.and_then(|route| {
+        route
+            .canonical_channels
+            .into_iter()
+            .find(|canonical| canonical.channel == channel)
+            .map(|canonical| canonical.chain)
+    });
+    if let Some(chain) = canonical_for {
+        if let Some(info) = CHAIN_REGISTRY.may_load(storage, &chain)? {
+            return Ok(Some(info));
+        }
+    }
+    match registered_chain_id(storage, channel)? {
+        Some(chain) => CHAIN_REGISTRY.may_load(storage, &chain),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bech32::{ToBase32, Variant};
+    use cosmwasm_std::testing::MockStorage;
+
+    use crate::state::CanonicalChannel;
+    use crate::test_helpers::{add_channel, remote_address};
+
+    fn osmosis(channel: &str) -> ChainInfo {
+        ChainInfo {
+            channel: channel.into(),
+            bech32_prefix: "osmo".into(),
+            address_lengths: vec![20, 32],
+        }
+    }
+
+    #[test]
+    fn addresses_need_the_prefix_checksum_and_length_of_the_chain() {
+        let chain = osmosis("channel-0");
+        chain.validate_address(&remote_address("osmo")).unwrap();
+
+        let invalid = |address: &str| {
+            matches!(
+                chain.validate_address(address),
+                Err(ContractError::InvalidRemoteAddress { .. })
+            )
+        };
+        assert!(invalid(&remote_address("cosmos")));
+        assert!(invalid("osmo1notbech32"));
+        let mut typo = remote_address("osmo");
+        typo.pop();
+        typo.push('q');
+        assert!(invalid(&typo));
+        let bech32m = bech32::encode("osmo", [7u8; 20].to_base32(), Variant::Bech32m).unwrap();
+        assert!(invalid(&bech32m));
+        let short = bech32::encode("osmo", [7u8; 8].to_base32(), Variant::Bech32).unwrap();
+        assert!(invalid(&short));
+    }
+
+    #[test]
+    fn channels_lead_to_their_chain_through_routes_and_connections() {
+        let mut storage = MockStorage::new();
+        add_channel(&mut storage, "channel-0", "connection-0");
+        add_channel(&mut storage, "channel-1", "connection-0");
+        add_channel(&mut storage, "channel-2", "connection-1");
+        add_channel(&mut storage, "channel-3", "connection-2");
+        CHAIN_REGISTRY
+            .save(&mut storage, "osmosis-1", &osmosis("channel-0"))
+            .unwrap();
+        let route = TokenRoute {
+            allowed_channels: vec![],
+            canonical_channels: vec![CanonicalChannel {
+                chain: "osmosis-1".into(),
+                channel: "channel-2".into(),
+            }],
+        };
+        TOKEN_ROUTES.save(&mut storage, "token", &route).unwrap();
+
+        let chain = |channel: &str| {
+            resolve_target(&storage, "token", Some(channel.into()), None)
+                .unwrap()
+                .1
+        };
+        assert_eq!(chain("channel-0"), Some(osmosis("channel-0")));
+        // another channel on the registered channel's connection
+        assert_eq!(chain("channel-1"), Some(osmosis("channel-0")));
+        // the token's canonical channel to the chain
+        assert_eq!(chain("channel-2"), Some(osmosis("channel-0")));
+        assert_eq!(chain("channel-3"), None);
+    }
+}
@@ -1,23 +1,346 @@
-use cosmwasm_std::{Addr, IbcEndpoint, StdResult, Storage, Uint128};
+use cosmwasm_std::{Addr, IbcEndpoint, Order, StdResult, Storage, Timestamp, Uint128};
+use schemars::JsonSchema;
 use secret_storage_plus::{Item, Map};
 use serde::{Deserialize, Serialize};
 
+use crate::msg::TransferMsg;
+use crate::padding::DEFAULT_BLOCK_SIZE;
 use crate::ContractError;
 
 pub const CODE_HASH: Map<Addr, String> = Map::new("code_hash");
 
+/// metadata of each registered token as of its registration, and whether it is active
+pub const TOKEN_DETAILS: Map<&Addr, TokenDetails> = Map::new("token_details");
+
+/// tokens waiting for the reply to their register_receive, by reply id
+pub const PENDING_REGISTRATIONS: Map<u64, Addr> = Map::new("pending_registrations");
+
+/// tokens waiting for the reply to the bridge's set_viewing_key, by reply id
+pub const PENDING_VIEWING_KEYS: Map<u64, Addr> = Map::new("pending_viewing_keys");
+
+/// admin, roles and switches of the bridge
+pub const CONFIG: Item<Config> = Item::new("config");
+
+/// seed the bridge's viewing keys are derived from
+pub const PRNG_SEED: Item<Vec<u8>> = Item::new("prng_seed");
+
+/// the viewing key of the bridge's account with every registered token, never exposed in queries
+pub const BRIDGE_VIEWING_KEY: Item<String> = Item::new("bridge_viewing_key");
+
+/// each user's transfers, refunds and receives, by (user, id)
+pub const USER_HISTORY: Map<(&Addr, u64), HistoryEntry> = Map::new("user_history");
+
+/// how many entries each user's history has, also the id of the next one
+pub const USER_HISTORY_COUNT: Map<&Addr, u64> = Map::new("user_history_count");
+
 // Used to pass info from the ibc_packet_receive to the reply handler
 pub const REPLY_ARGS: Item<ReplyArgs> = Item::new("reply_args");
 
 /// static info on one channel that doesn't change
 pub const CHANNEL_INFO: Map<&str, ChannelInfo> = Map::new("channel_info");
 
+/// connections that channels may be opened on
+pub const ALLOWED_CONNECTIONS: Map<&str, bool> = Map::new("allowed_connections");
+
+/// counterparty ports that channels may be opened to, normally "transfer"
+pub const ALLOWED_COUNTERPARTY_PORTS: Map<&str, bool> = Map::new("allowed_counterparty_ports");
+
+/// counterparty chains that transfers can target by chain id instead of channel
+pub const CHAIN_REGISTRY: Map<&str, ChainInfo> = Map::new("chain_registry");
+
+/// the chain id every channel was registered for, kept when the chain moves to another channel
+pub const CHANNEL_CHAINS: Map<&str, String> = Map::new("channel_chains");
+
+/// per token, the channels it may be sent over. Tokens without a route may use any channel
+pub const TOKEN_ROUTES: Map<&str, TokenRoute> = Map::new("token_routes");
+
+/// retired channels, pointing at the channel their escrow was moved to
+pub const RETIRED_CHANNELS: Map<&str, String> = Map::new("retired_channels");
+
+/// the retired channels whose vouchers a channel honors
+pub const PREDECESSORS: Map<&str, Vec<String>> = Map::new("channel_predecessors");
+
+/// channel migrations waiting out their time lock, by the channel to retire
+pub const PENDING_MIGRATIONS: Map<&str, PendingMigration> = Map::new("pending_migrations");
+
+/// every executed channel migration, in order
+pub const MIGRATION_LOG: Map<u64, MigrationRecord> = Map::new("migration_log");
+
 /// indexed by (channel_id, denom) maintaining the balance of the channel in that currency
 pub const CHANNEL_STATE: Map<(&str, &str), ChannelState> = Map::new("channel_state");
 
+/// indexed by (channel_id, denom), the rate limits applied to transfers on that path
+pub const RATE_LIMITS: Map<(&str, &str), Vec<RateLimit>> = Map::new("rate_limits");
+
+/// per token, how much one address may send out during its window
+pub const ADDRESS_QUOTAS: Map<&str, AddressQuota> = Map::new("address_quotas");
+
+/// indexed by (token, address), replaces the token's address quota for allow-listed addresses
+pub const ADDRESS_QUOTA_OVERRIDES: Map<(&str, &str), QuotaOverride> =
+    Map::new("address_quota_overrides");
+
+/// indexed by (token, address), what the address sent during its rolling window
+pub const ADDRESS_USAGE: Map<(&str, &str), Flow> = Map::new("address_usage");
+
+/// per token, caps on how much of it may sit in escrow
+pub const ESCROW_CAPS: Map<&str, EscrowCap> = Map::new("escrow_caps");
+
+/// indexed by (channel_id, denom), replaces the per channel cap of the token on that channel
+pub const CHANNEL_ESCROW_CAPS: Map<(&str, &str), Uint128> = Map::new("channel_escrow_caps");
+
+/// per token, a fee schedule that replaces the default one
+pub const TOKEN_FEES: Map<&str, FeeSchedule> = Map::new("token_fees");
+
+/// per token, fees kept in the contract that the collector has not claimed yet
+pub const ACCRUED_FEES: Map<&str, Uint128> = Map::new("accrued_fees");
+
+/// tokens that support SNIP20 decoys, with how many to add to their unlock and refund transfers
+pub const TOKEN_DECOYS: Map<&str, u8> = Map::new("token_decoys");
+
+/// addresses set by the admin to use as decoys
+pub const DECOY_POOL: Item<Vec<Addr>> = Item::new("decoy_pool");
+
+/// the latest addresses to use the bridge, also used as decoys
+pub const RECENT_USERS: Item<Vec<Addr>> = Item::new("recent_users");
+
+/// the secret every user's SNIP-52 notification seed is derived from, never exposed
+pub const NOTIFICATION_SECRET: Item<Vec<u8>> = Item::new("notification_secret");
+
+/// how many notifications each user got on each SNIP-52 channel, by (user, channel)
+pub const NOTIFICATION_COUNTERS: Map<(&Addr, &str), u64> = Map::new("notification_counters");
+
+/// tokens whose channel balances and statistics are only shown exactly to privileged viewers
+pub const PRIVATE_STATS: Map<&str, StatsVisibility> = Map::new("private_stats");
+
+/// outbound transfers waiting for the end of the epoch, by queue id
+pub const QUEUED_TRANSFERS: Map<u64, QueuedTransfer> = Map::new("queued_transfers");
+
+/// the id the next queued transfer gets
+pub const NEXT_QUEUE_ID: Item<u64> = Item::new("next_queue_id");
+
+/// queued transfers whose release failed, by queue id, until their refund goes through.
+/// Those whose refund failed too stay here for their sender to cancel
+pub const FAILED_RELEASES: Map<u64, QueuedTransfer> = Map::new("failed_releases");
+
+/// when the current epoch ends and queued transfers may be released
+pub const EPOCH_END: Item<Timestamp> = Item::new("epoch_end");
+
+/// indexed by (channel_id, hash of the packet data), who sent the packets that are still in
+/// flight, oldest first. Identical packets share the entry
+pub const PACKET_SENDERS: Map<(&str, &[u8]), Vec<Addr>> = Map::new("in_flight_senders");
+
+/// indexed by (channel_id, hash of the packet data), the fees charged on outgoing packets that
+/// are still in flight, oldest first. Identical packets share the entry
+pub const PACKET_FEES: Map<(&str, &[u8]), Vec<Uint128>> = Map::new("in_flight_fees");
+
+/// indexed by (relayer, token), rewards the relayer has not claimed yet
+pub const RELAYER_REWARDS: Map<(&Addr, &str), Uint128> = Map::new("relayer_rewards");
+
+/// how many packets each relayer delivered to us
+pub const RELAYER_STATS: Map<&Addr, RelayerStats> = Map::new("relayer_stats");
+
 /// Every snip20 contract we allow to be sent is stored here, possibly with a gas_limit
 //pub const ALLOW_LIST: Map<&Addr, AllowInfo> = Map::new("allow_list");
 
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct Config {
+    /// can change the config and is the only one allowed to unpause
+    pub admin: Addr,
+    /// can pause the bridge during an incident, but not unpause it
+    pub pauser: Option<Addr>,
+    pub paused: PauseState,
+    #[serde(default)]
+    pub fees: FeeConfig,
+    #[serde(default)]
+    pub relayer_incentive: RelayerIncentive,
+    /// reject outbound transfers of a token while its escrow is short of what the bridge owes
+    #[serde(default)]
+    pub escrow_guard: bool,
+    /// keep addresses and amounts of transfers out of event attributes
+    #[serde(default)]
+    pub private_events: bool,
+    /// response data and messages to tokens are padded to a multiple of this many bytes
+    #[serde(default = "default_block_size")]
+    pub block_size: u16,
+    /// when set, transfers are queued and released together once per epoch of this many seconds
+    #[serde(default)]
+    pub batch_epoch: Option<u64>,
+}
+
+fn default_block_size() -> u16 {
+    DEFAULT_BLOCK_SIZE
+}
+
+/// Rewards for relayers are paid out of the accrued fees of the packet's token
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema, Default)]
+#[serde(rename_all = "snake_case")]
+pub struct RelayerIncentive {
+    /// share of the fee charged on an outgoing packet, paid when its ack or timeout is delivered
+    pub fee_share_bps: u16,
+    /// paid for every delivered receive, ack or timeout
+    pub flat: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema, Default)]
+#[serde(rename_all = "snake_case")]
+pub struct FeeConfig {
+    /// applies to every token without its own schedule
+    pub default: FeeSchedule,
+    /// receives the fees, required as soon as any fee is charged
+    pub collector: Option<Addr>,
+    /// keep fees in the contract for the collector to claim, instead of transferring each one
+    pub accrue: bool,
+    /// refund the fee together with the amount when a packet fails, only possible while accruing
+    pub refund_fee: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema, Default)]
+#[serde(rename_all = "snake_case")]
+pub struct FeeSchedule {
+    /// fee in basis points of the transferred amount
+    pub bps: u16,
+    pub min_fee: Uint128,
+    pub max_fee: Option<Uint128>,
+    /// transfers below this amount are rejected as dust
+    pub min_transfer: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema, Default)]
+#[serde(rename_all = "snake_case")]
+pub struct PauseState {
+    /// reject new outgoing transfers
+    pub outbound: bool,
+    /// reject incoming packets that unlock escrowed tokens
+    pub inbound: bool,
+    /// hold back refunds of failed or timed out packets until unpaused
+    pub refunds: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct Quota {
+    /// identifies the quota among the ones on the same path
+    pub name: String,
+    /// length of the rolling window in seconds
+    pub duration: u64,
+    /// max outflow per window, in percent of the escrow on the path before the transfer.
+    /// Does not apply while nothing is escrowed, so a fresh path can be used at all
+    pub max_percentage_send: Option<u32>,
+    /// max inflow per window, in percent of the escrow on the path before the transfer.
+    /// Nothing may come in while nothing is escrowed
+    pub max_percentage_recv: Option<u32>,
+    /// max outflow per window in token units
+    pub max_amount_send: Option<Uint128>,
+    /// max inflow per window in token units
+    pub max_amount_recv: Option<Uint128>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema, Default)]
+#[serde(rename_all = "snake_case")]
+pub struct Flow {
+    /// what moved during the window, oldest first. Flows recorded before the windows
+    /// started rolling are dropped
+    #[serde(default)]
+    pub buckets: Vec<FlowBucket>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct FlowBucket {
+    /// the bucket holds the flows from this time on, until the next one starts
+    pub start: Timestamp,
+    pub inflow: Uint128,
+    pub outflow: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct RateLimit {
+    pub quota: Quota,
+    pub flow: Flow,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct AddressQuota {
+    /// max amount one address may send per window
+    pub max_amount: Uint128,
+    /// length of the rolling window in seconds, e.g. 86400 for a daily quota
+    pub window: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct QuotaOverride {
+    /// custom max amount per window, or None for no limit at all
+    pub max_amount: Option<Uint128>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema, Default)]
+#[serde(rename_all = "snake_case")]
+pub struct RelayerStats {
+    pub receives: u64,
+    pub acks: u64,
+    pub timeouts: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct ChainInfo {
+    /// the channel transfers to this chain go over by default
+    pub channel: String,
+    pub bech32_prefix: String,
+    /// byte lengths a decoded address may have, any length if empty
+    #[serde(default)]
+    pub address_lengths: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct TokenRoute {
+    /// channels the token may be sent over besides the canonical ones
+    pub allowed_channels: Vec<String>,
+    /// the one channel per counterparty chain that keeps the token's voucher fungible there
+    pub canonical_channels: Vec<CanonicalChannel>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct CanonicalChannel {
+    /// chain id of the counterparty
+    pub chain: String,
+    pub channel: String,
+}
+
+impl TokenRoute {
+    pub fn permits(&self, channel: &str) -> bool {
+        self.allowed_channels
+            .iter()
+            .any(|allowed| allowed == channel)
+            || self
+                .canonical_channels
+                .iter()
+                .any(|canonical| canonical.channel == channel)
+    }
+
+    pub fn canonical_channel(&self, chain: &str) -> Option<&str> {
+        self.canonical_channels
+            .iter()
+            .find(|canonical| canonical.chain == chain)
+            .map(|canonical| canonical.channel.as_str())
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema, Default)]
+#[serde(rename_all = "snake_case")]
+pub struct EscrowCap {
+    /// max outstanding summed over all channels
+    pub total: Option<Uint128>,
+    /// max outstanding on any single channel, unless the channel has its own cap
+    pub per_channel: Option<Uint128>,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, Default)]
 #[serde(rename_all = "snake_case")]
 pub struct ChannelState {
@@ -34,6 +357,44 @@ pub struct ChannelInfo {
     pub counterparty_endpoint: IbcEndpoint,
     /// the connection this exists on (you can use to query client/consensus info)
     pub connection_id: String,
+    /// whether the channel runs the ICS-29 fee middleware
+    #[serde(default)]
+    pub fee_enabled: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub struct QueuedTransfer {
+    pub msg: TransferMsg,
+    pub token: Addr,
+    pub amount: Uint128,
+    pub sender: Addr,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct PendingMigration {
+    pub retired: String,
+    pub successor: String,
+    /// the migration cannot be completed before this time
+    pub not_before: Timestamp,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct MigrationRecord {
+    pub retired: String,
+    pub successor: String,
+    pub executed_at: Timestamp,
+    /// the outstanding balances that were moved
+    pub balances: Vec<MovedBalance>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct MovedBalance {
+    pub token: String,
+    pub amount: Uint128,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
@@ -49,6 +410,140 @@ pub struct ReplyArgs {
     pub channel: String,
     pub denom: String,
     pub amount: Uint128,
+    /// the user whose history got an entry for this transfer
+    pub receiver: Addr,
+    /// who got relayer_reward for delivering the packet, taken back if the unlock fails
+    pub relayer: Option<Addr>,
+    pub relayer_reward: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct TokenDetails {
+    pub name: String,
+    pub symbol: String,
+    pub decimals: u8,
+    /// None if the token keeps its supply private
+    pub total_supply: Option<Uint128>,
+    pub status: TokenStatus,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Eq, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+/// Only active tokens may be sent or received; refunds of locked tokens are always attempted
+pub enum TokenStatus {
+    /// registered, but the token did not confirm register_receive and the bridge's viewing key yet
+    Pending,
+    /// the token sends its transfers to the bridge
+    Active,
+    /// a transfer of the token failed, maybe because it was migrated to a new code hash.
+    /// Updating its code hash activates it again
+    Failing,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct StatsVisibility {
+    /// the token's issuer, who sees exact figures like the admin
+    pub issuer: Option<Addr>,
+    /// others who see exact figures
+    pub auditors: Vec<Addr>,
+    /// what everyone else sees
+    pub public_view: PublicView,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Eq, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PublicView {
+    /// amounts rounded down to their leading digit
+    Bucketed,
+    /// no figures at all
+    Hidden,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Eq, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum HistoryAction {
+    /// sent out over IBC
+    Transfer,
+    /// returned after a failed transfer
+    Refund,
+    /// received over IBC
+    Receive,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct HistoryEntry {
+    pub id: u64,
+    pub action: HistoryAction,
+    pub token: String,
+    pub amount: Uint128,
+    pub channel: String,
+    /// the address on the other chain
+    pub counterparty: String,
+    pub time: Timestamp,
+}
+
+/// Remembers who sent a packet, as it may carry a stand-in. Identical packets cannot be told
+/// apart once they come back, so their senders are queued and released first in, first out.
+/// Refusing a second one instead would let anyone block others' transfers by sending theirs.
+pub fn hold_packet_sender(
+    storage: &mut dyn Storage,
+    channel: &str,
+    packet_key: &[u8],
+    sender: &Addr,
+) -> StdResult<()> {
+    let mut senders = PACKET_SENDERS
+        .may_load(storage, (channel, packet_key))?
+        .unwrap_or_default();
+    senders.push(sender.clone());
+    PACKET_SENDERS.save(storage, (channel, packet_key), &senders)?;
+    Ok(())
+}
+
+/// The sender held for the packet, None for packets sent before senders were held
+pub fn release_packet_sender(
+    storage: &mut dyn Storage,
+    channel: &str,
+    packet_key: &[u8],
+) -> StdResult<Option<Addr>> {
+    let mut senders = match PACKET_SENDERS.may_load(storage, (channel, packet_key))? {
+        Some(senders) => senders,
+        None => return Ok(None),
+    };
+    let sender = senders.remove(0);
+    if senders.is_empty() {
+        PACKET_SENDERS.remove(storage, (channel, packet_key));
+    } else {
+        PACKET_SENDERS.save(storage, (channel, packet_key), &senders)?;
+    }
+    Ok(Some(sender))
+}
+
+/// Sum of the outstanding balance of denom over all channels
+pub fn total_outstanding(storage: &dyn Storage, denom: &str) -> StdResult<Uint128> {
+    let channels: Vec<String> = CHANNEL_INFO
+        .keys(storage, None, None, Order::Ascending)
+        .collect::<StdResult<_>>()?;
+    channels.iter().try_fold(Uint128::zero(), |total, channel| {
+        let state = CHANNEL_STATE.may_load(storage, (channel, denom))?;
+        Ok(total + state.unwrap_or_default().outstanding)
+    })
+}
+
+/// The cap on the outstanding balance of denom on this channel, if any
+pub fn channel_escrow_cap(
+    storage: &dyn Storage,
+    channel: &str,
+    denom: &str,
+) -> StdResult<Option<Uint128>> {
+    match CHANNEL_ESCROW_CAPS.may_load(storage, (channel, denom))? {
+        Some(cap) => Ok(Some(cap)),
+        None => Ok(ESCROW_CAPS
+            .may_load(storage, denom)?
+            .and_then(|cap| cap.per_channel)),
+    }
 }
 
 pub fn increase_channel_balance(
@@ -57,12 +552,35 @@ pub fn increase_channel_balance(
     denom: &str,
     amount: Uint128,
 ) -> Result<(), ContractError> {
-    CHANNEL_STATE.update(storage, (channel, denom), |orig| -> StdResult<_> {
+    let state = CHANNEL_STATE.update(storage, (channel, denom), |orig| -> StdResult<_> {
         let mut state = orig.unwrap_or_default();
         state.outstanding += amount;
         state.total_sent += amount;
         Ok(state)
     })?;
+
+    // caps are checked after the update, so the new amount is already part of the totals
+    if let Some(cap) = channel_escrow_cap(storage, channel, denom)? {
+        if state.outstanding > cap {
+            return Err(ContractError::EscrowCapExceeded {
+                token: denom.into(),
+                scope: format!("channel {}", channel),
+                cap,
+            });
+        }
+    }
+    if let Some(cap) = ESCROW_CAPS
+        .may_load(storage, denom)?
+        .and_then(|cap| cap.total)
+    {
+        if total_outstanding(storage, denom)? > cap {
+            return Err(ContractError::EscrowCapExceeded {
+                token: denom.into(),
+                scope: "bridge".into(),
+                cap,
+            });
+        }
+    }
     Ok(())
 }
 
@@ -111,3 +629,68 @@ pub fn undo_reduce_channel_balance(
     })?;
     Ok(())
 }
+
+/// Follows retired channels to the channel that now holds their escrow
+pub fn active_channel(storage: &dyn Storage, channel: &str) -> StdResult<String> {
+    let mut channel = channel.to_string();
+    while let Some(successor) = RETIRED_CHANNELS.may_load(storage, &channel)? {
+        channel = successor;
+    }
+    Ok(channel)
+}
+
+/// Moves the outstanding balance of every token from the retired channel onto its successor,
+/// which from now on honors the retired channel's vouchers. The move is added to the audit log.
+pub fn migrate_channel_balances(
+    storage: &mut dyn Storage,
+    now: Timestamp,
+    retired: &str,
+    successor: &str,
+) -> StdResult<MigrationRecord> {
+    let balances: Vec<(String, ChannelState)> = CHANNEL_STATE
+        .prefix(retired)
+        .range(storage, None, None, Order::Ascending)
+        .collect::<StdResult<_>>()?;
+
+    let mut moved = vec![];
+    for (denom, mut state) in balances {
+        if state.outstanding.is_zero() {
+            continue;
+        }
+        // escrow caps are not checked, the tokens are already escrowed
+        CHANNEL_STATE.update(storage, (successor, &denom), |orig| -> StdResult<_> {
+            let mut successor_state = orig.unwrap_or_default();
+            successor_state.outstanding += state.outstanding;
+            Ok(successor_state)
+        })?;
+        moved.push(MovedBalance {
+            token: denom.clone(),
+            amount: state.outstanding,
+        });
+        state.outstanding = Uint128::zero();
+        CHANNEL_STATE.save(storage, (retired, &denom), &state)?;
+    }
+
+    RETIRED_CHANNELS.save(storage, retired, &successor.to_string())?;
+    let mut predecessors = PREDECESSORS
+        .may_load(storage, successor)?
+        .unwrap_or_default();
+    predecessors.push(retired.to_string());
+    predecessors.extend(PREDECESSORS.may_load(storage, retired)?.unwrap_or_default());
+    PREDECESSORS.save(storage, successor, &predecessors)?;
+    PREDECESSORS.remove(storage, retired);
+
+    let index = MIGRATION_LOG
+        .keys(storage, None, None, Order::Descending)
+        .next()
+        .transpose()?
+        .map_or(0, |last| last + 1);
+    let record = MigrationRecord {
+        retired: retired.to_string(),
+        successor: successor.to_string(),
+        executed_at: now,
+        balances: moved,
+    };
+    MIGRATION_LOG.save(storage, index, &record)?;
+    Ok(record)
+}
@@ -0,0 +1,160 @@
+use cosmwasm_std::{Addr, StdError, StdResult, Storage, Uint128};
+
+use crate::msg::ReconcileResponse;
+use crate::state::{PublicView, RateLimit, CONFIG, PRIVATE_STATS};
+
+/// How much of a token's channel balances and statistics a querier may see
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum StatsAccess {
+    Exact,
+    Bucketed,
+    Denied,
+}
+
+/// The admin, the token's issuer and its auditors see exact figures, as does everyone for
+/// tokens that did not opt into private statistics. Pass the viewer only once authenticated.
+pub fn stats_access(
+    storage: &dyn Storage,
+    token: &str,
+    viewer: Option<&Addr>,
+) -> StdResult<StatsAccess> {
+    let visibility = match PRIVATE_STATS.may_load(storage, token)? {
+        Some(visibility) => visibility,
+        None => return Ok(StatsAccess::Exact),
+    };
+    if let Some(viewer) = viewer {
+        if *viewer == CONFIG.load(storage)?.admin
+            || visibility.issuer.as_ref() == Some(viewer)
+            || visibility.auditors.contains(viewer)
+        {
+            return Ok(StatsAccess::Exact);
+        }
+    }
+    Ok(match visibility.public_view {
+        PublicView::Bucketed => StatsAccess::Bucketed,
+        PublicView::Hidden => StatsAccess::Denied,
+    })
+}
+
+/// Keeps only the leading digit of the amount, e.g. 12345 becomes 10000
+pub fn bucket(amount: Uint128) -> Uint128 {
+    let amount = amount.u128();
+    let mut magnitude = 1u128;
+    while let Some(next) = magnitude.checked_mul(10) {
+        if next > amount {
+            break;
+        }
+        magnitude = next;
+    }
+    Uint128::new(amount - amount % magnitude)
+}
+
+/// The amount as the querier may see it
+pub fn reveal(access: StatsAccess, amount: Uint128) -> StdResult<Uint128> {
+    match access {
+        StatsAccess::Exact => Ok(amount),
+        StatsAccess::Bucketed => Ok(bucket(amount)),
+        StatsAccess::Denied => Err(StdError::generic_err(
+            "Statistics of this token are private",
+        )),
+    }
+}
+
+pub fn reveal_rate_limit(access: StatsAccess, mut limit: RateLimit) -> StdResult<RateLimit> {
+    for bucket in limit.flow.buckets.iter_mut() {
+        bucket.inflow = reveal(access, bucket.inflow)?;
+        bucket.outflow = reveal(access, bucket.outflow)?;
+    }
+    Ok(limit)
+}
+
+pub fn reveal_reconcile(
+    access: StatsAccess,
+    response: ReconcileResponse,
+) -> StdResult<ReconcileResponse> {
+    Ok(ReconcileResponse {
+        balance: reveal(access, response.balance)?,
+        outstanding: reveal(access, response.outstanding)?,
+        accrued_fees: reveal(access, response.accrued_fees)?,
+        relayer_rewards: reveal(access, response.relayer_rewards)?,
+        queued: reveal(access, response.queued)?,
+        liabilities: reveal(access, response.liabilities)?,
+        surplus: reveal(access, response.surplus)?,
+        deficit: reveal(access, response.deficit)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cosmwasm_std::testing::mock_dependencies;
+
+    use crate::state::StatsVisibility;
+    use crate::test_helpers::{setup, ADMIN};
+
+    fn private_stats(storage: &mut dyn Storage, public_view: PublicView) {
+        let visibility = StatsVisibility {
+            issuer: Some(Addr::unchecked("issuer")),
+            auditors: vec![Addr::unchecked("auditor")],
+            public_view,
+        };
+        PRIVATE_STATS.save(storage, "token", &visibility).unwrap();
+    }
+
+    #[test]
+    fn buckets_keep_the_leading_digit() {
+        assert_eq!(bucket(Uint128::zero()), Uint128::zero());
+        assert_eq!(bucket(Uint128::new(7)), Uint128::new(7));
+        assert_eq!(bucket(Uint128::new(10)), Uint128::new(10));
+        assert_eq!(bucket(Uint128::new(12345)), Uint128::new(10000));
+        assert_eq!(bucket(Uint128::new(99999)), Uint128::new(90000));
+        assert_eq!(
+            bucket(Uint128::MAX),
+            Uint128::new(300000000000000000000000000000000000000)
+        );
+    }
+
+    #[test]
+    fn privileged_viewers_see_exact_figures() {
+        let mut deps = mock_dependencies();
+        setup(deps.as_mut());
+        private_stats(&mut deps.storage, PublicView::Bucketed);
+
+        for viewer in [ADMIN, "issuer", "auditor"] {
+            let viewer = Addr::unchecked(viewer);
+            assert_eq!(
+                stats_access(&deps.storage, "token", Some(&viewer)).unwrap(),
+                StatsAccess::Exact
+            );
+        }
+        let stranger = Addr::unchecked("stranger");
+        assert_eq!(
+            stats_access(&deps.storage, "token", Some(&stranger)).unwrap(),
+            StatsAccess::Bucketed
+        );
+        assert_eq!(
+            stats_access(&deps.storage, "token", None).unwrap(),
+            StatsAccess::Bucketed
+        );
+        // tokens that did not opt in are public
+        assert_eq!(
+            stats_access(&deps.storage, "other", None).unwrap(),
+            StatsAccess::Exact
+        );
+    }
+
+    #[test]
+    fn hidden_figures_are_denied() {
+        let mut deps = mock_dependencies();
+        setup(deps.as_mut());
+        private_stats(&mut deps.storage, PublicView::Hidden);
+
+        let access = stats_access(&deps.storage, "token", None).unwrap();
+        assert_eq!(access, StatsAccess::Denied);
+        reveal(access, Uint128::new(5)).unwrap_err();
+        assert_eq!(
+            reveal(StatsAccess::Bucketed, Uint128::new(55)).unwrap(),
+            Uint128::new(50)
+        );
+    }
+}
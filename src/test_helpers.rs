@@ -0,0 +1,166 @@
+//! Fixtures shared by the unit tests of the modules
+
+use bech32::{ToBase32, Variant};
+use cosmwasm_std::testing::{mock_env, mock_info, MockQuerier};
+use cosmwasm_std::{
+    from_binary, from_slice, to_binary, Addr, Binary, ContractResult, CosmosMsg, DepsMut,
+    IbcEndpoint, IbcMsg, Response, Storage, SystemResult, Uint128, WasmMsg, WasmQuery,
+};
+use serde::Deserialize;
+
+use crate::contract::{execute, instantiate};
+use crate::ibc::Ics20Packet;
+use crate::msg::{ExecuteMsg, InitMsg, SenderMode, Snip20ReceiveMsg, TransferMsg};
+use crate::state::{ChannelInfo, QueuedTransfer, CHANNEL_INFO, CODE_HASH, QUEUED_TRANSFERS};
+
+pub const ADMIN: &str = "admin";
+
+/// The port and channel packets of mock_ibc_packet_recv come from
+pub const REMOTE_PORT: &str = "their-port";
+pub const REMOTE_CHANNEL: &str = "channel-1234";
+
+/// Instantiates the bridge with ADMIN as its admin
+pub fn setup(deps: DepsMut) {
+    instantiate(
+        deps,
+        mock_env(),
+        mock_info(ADMIN, &[]),
+        InitMsg {
+            admin: None,
+            pauser: None,
+            entropy: None,
+            block_size: None,
+            allowed_connections: vec!["connection-0".into()],
+            allowed_counterparty_ports: vec!["transfer".into()],
+        },
+    )
+    .unwrap();
+}
+
+/// Stores an open channel to the remote endpoint of mock packets
+pub fn add_channel(storage: &mut dyn Storage, id: &str, connection_id: &str) {
+    let info = ChannelInfo {
+        id: id.into(),
+        counterparty_endpoint: IbcEndpoint {
+            port_id: REMOTE_PORT.into(),
+            channel_id: REMOTE_CHANNEL.into(),
+        },
+        connection_id: connection_id.into(),
+        fee_enabled: false,
+    };
+    CHANNEL_INFO.save(storage, id, &info).unwrap();
+}
+
+/// Registers the token without querying it, as tokens of older deployments were
+pub fn add_token(storage: &mut dyn Storage, token: &str) {
+    CODE_HASH
+        .save(storage, Addr::unchecked(token), &"hash".to_string())
+        .unwrap();
+}
+
+/// Answers SNIP20 queries as a token holding balance for the bridge
+pub fn mock_token(querier: &mut MockQuerier, balance: u128) {
+    querier.update_wasm(move |query| {
+        let msg = match query {
+            WasmQuery::Smart { msg, .. } => msg.as_slice(),
+            _ => panic!("unexpected query {:?}", query),
+        };
+        let answer = if msg.starts_with(br#"{"balance""#) {
+            format!(r#"{{"balance":{{"amount":"{}"}}}}"#, balance)
+        } else {
+            r#"{"token_info":{"name":"Token","symbol":"TKN","decimals":6,"total_supply":null}}"#
+                .to_string()
+        };
+        SystemResult::Ok(ContractResult::Ok(Binary::from(answer.as_bytes())))
+    });
+}
+
+/// A valid bech32 account address on a chain with the prefix
+pub fn remote_address(prefix: &str) -> String {
+    bech32::encode(prefix, [7u8; 20].to_base32(), Variant::Bech32).unwrap()
+}
+
+pub fn transfer_msg(channel: &str, remote_address: &str) -> TransferMsg {
+    TransferMsg {
+        channel: Some(channel.into()),
+        chain: None,
+        remote_address: remote_address.into(),
+        timeout: 600,
+        sender_mode: SenderMode::Address,
+    }
+}
+
+/// What the token sends the bridge when sender sends it amount with the transfer message
+pub fn receive_msg(sender: &str, amount: u128, msg: &TransferMsg) -> ExecuteMsg {
+    ExecuteMsg::Receive(Snip20ReceiveMsg {
+        sender: sender.into(),
+        from: sender.into(),
+        amount: Uint128::new(amount),
+        memo: None,
+        msg: Some(to_binary(msg).unwrap()),
+    })
+}
+
+/// Queues alice's transfer of 100 token over channel-0
+pub fn add_queued_transfer(storage: &mut dyn Storage, id: u64) {
+    let queued = QueuedTransfer {
+        msg: transfer_msg("channel-0", "remote"),
+        token: Addr::unchecked("token"),
+        amount: Uint128::new(100),
+        sender: Addr::unchecked("alice"),
+    };
+    QUEUED_TRANSFERS.save(storage, id, &queued).unwrap();
+}
+
+/// Has sender send amount of token over the channel and returns the packet that went out
+pub fn send_transfer(
+    deps: DepsMut,
+    token: &str,
+    sender: &str,
+    amount: u128,
+    msg: &TransferMsg,
+) -> Ics20Packet {
+    let res = execute(
+        deps,
+        mock_env(),
+        mock_info(token, &[]),
+        receive_msg(sender, amount, msg),
+    )
+    .unwrap();
+    sent_packet(&res)
+}
+
+pub fn sent_packet(res: &Response) -> Ics20Packet {
+    res.messages
+        .iter()
+        .find_map(|sub| match &sub.msg {
+            CosmosMsg::Ibc(IbcMsg::SendPacket { data, .. }) => Some(from_binary(data).unwrap()),
+            _ => None,
+        })
+        .expect("no packet sent")
+}
+
+#[derive(Deserialize)]
+struct Snip20Transfer {
+    recipient: String,
+    amount: Uint128,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Snip20Msg {
+    Transfer(Snip20Transfer),
+}
+
+/// The token, recipient and amount of a SNIP20 transfer the bridge sends
+pub fn snip20_transfer(msg: &CosmosMsg) -> (String, String, Uint128) {
+    match msg {
+        CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr, msg, ..
+        }) => {
+            let Snip20Msg::Transfer(transfer) = from_slice(msg.as_slice()).unwrap();
+            (contract_addr.clone(), transfer.recipient, transfer.amount)
+        }
+        other => panic!("not a token transfer: {:?}", other),
+    }
+}
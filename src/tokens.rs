@@ -0,0 +1,223 @@
+use cosmwasm_std::{
+    Addr, Deps, DepsMut, Response, StdError, StdResult, Storage, SubMsgResult, Uint128,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::error::ContractError;
+use crate::state::{
+    TokenDetails, TokenStatus, PENDING_REGISTRATIONS, PENDING_VIEWING_KEYS, TOKEN_DETAILS,
+};
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Snip20QueryMsg {
+    Balance { address: String, key: String },
+    TokenInfo {},
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Snip20QueryAnswer {
+    Balance {
+        amount: Uint128,
+    },
+    TokenInfo {
+        name: String,
+        symbol: String,
+        decimals: u8,
+        /// None if the token keeps its supply private
+        total_supply: Option<Uint128>,
+    },
+}
+
+/// Asks the token for its metadata, which also proves the code hash is right.
+/// The details start out pending until the token confirms register_receive.
+pub fn query_token_details(deps: Deps, code_hash: &str, token: &Addr) -> StdResult<TokenDetails> {
+    let answer: Snip20QueryAnswer =
+        deps.querier
+            .query_wasm_smart(code_hash, token, &Snip20QueryMsg::TokenInfo {})?;
+    match answer {
+        Snip20QueryAnswer::TokenInfo {
+            name,
+            symbol,
+            decimals,
+            total_supply,
+        } => Ok(TokenDetails {
+            name,
+            symbol,
+            decimals,
+            total_supply,
+            status: TokenStatus::Pending,
+        }),
+        _ => Err(StdError::generic_err("Unexpected answer to token_info")),
+    }
+}
+
+/// Tokens move only while active. Tokens registered before their metadata was stored are
+/// left alone.
+pub fn assert_token_active(storage: &dyn Storage, token: &Addr) -> Result<(), ContractError> {
+    match TOKEN_DETAILS.may_load(storage, token)? {
+        Some(details) if details.status != TokenStatus::Active => {
+            Err(ContractError::TokenNotActive {
+                token: token.to_string(),
+                status: details.status,
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Marks the token failing after a transfer of it was rejected, so its code hash gets checked.
+/// Tokens registered before their metadata was stored are left alone.
+pub fn flag_failing_token(storage: &mut dyn Storage, token: &Addr) -> StdResult<()> {
+    if let Some(mut details) = TOKEN_DETAILS.may_load(storage, token)? {
+        details.status = TokenStatus::Failing;
+        TOKEN_DETAILS.save(storage, token, &details)?;
+    }
+    Ok(())
+}
+
+/// Activates the token once its register_receive went through.
+/// On failure it stays pending until the admin registers it again.
+pub fn on_token_registered(
+    deps: DepsMut,
+    id: u64,
+    result: SubMsgResult,
+) -> Result<Response, ContractError> {
+    let token = PENDING_REGISTRATIONS.load(deps.storage, id)?;
+    PENDING_REGISTRATIONS.remove(deps.storage, id);
+
+    match result {
+        SubMsgResult::Ok(_) => {
+            TOKEN_DETAILS.update(deps.storage, &token, |details| -> StdResult<_> {
+                let mut details = details.ok_or_else(|| StdError::not_found("TokenDetails"))?;
+                details.status = TokenStatus::Active;
+                Ok(details)
+            })?;
+            Ok(Response::new()
+                .add_attribute("action", "token_registered")
+                .add_attribute("token", token))
+        }
+        SubMsgResult::Err(err) => Ok(Response::new()
+            .add_attribute("action", "token_registration_failed")
+            .add_attribute("token", token)
+            .add_attribute("error", err)),
+    }
+}
+
+/// The bridge's viewing key is set after register_receive, so its reply comes last.
+/// Without the key the bridge cannot read its balance, so the token goes back to pending.
+pub fn on_viewing_key_set(
+    deps: DepsMut,
+    id: u64,
+    result: SubMsgResult,
+) -> Result<Response, ContractError> {
+    let token = PENDING_VIEWING_KEYS.load(deps.storage, id)?;
+    PENDING_VIEWING_KEYS.remove(deps.storage, id);
+
+    match result {
+        SubMsgResult::Ok(_) => Ok(Response::new()
+            .add_attribute("action", "viewing_key_set")
+            .add_attribute("token", token)),
+        SubMsgResult::Err(err) => {
+            TOKEN_DETAILS.update(deps.storage, &token, |details| -> StdResult<_> {
+                let mut details = details.ok_or_else(|| StdError::not_found("TokenDetails"))?;
+                details.status = TokenStatus::Pending;
+                Ok(details)
+            })?;
+            Ok(Response::new()
+                .add_attribute("action", "viewing_key_failed")
+                .add_attribute("token", token)
+                .add_attribute("error", err))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cosmwasm_std::testing::mock_dependencies;
+    use cosmwasm_std::SubMsgResponse;
+
+    fn token_with_details(storage: &mut dyn Storage, status: TokenStatus) -> Addr {
+        let token = Addr::unchecked("token");
+        let details = TokenDetails {
+            name: "Token".into(),
+            symbol: "TKN".into(),
+            decimals: 6,
+            total_supply: None,
+            status,
+        };
+        TOKEN_DETAILS.save(storage, &token, &details).unwrap();
+        PENDING_REGISTRATIONS.save(storage, 1, &token).unwrap();
+        PENDING_VIEWING_KEYS.save(storage, 2, &token).unwrap();
+        token
+    }
+
+    fn ok() -> SubMsgResult {
+        SubMsgResult::Ok(SubMsgResponse {
+            events: vec![],
+            data: None,
+        })
+    }
+
+    #[test]
+    fn registered_tokens_become_active() {
+        let mut deps = mock_dependencies();
+        let token = token_with_details(&mut deps.storage, TokenStatus::Pending);
+
+        on_token_registered(deps.as_mut(), 1, ok()).unwrap();
+        on_viewing_key_set(deps.as_mut(), 2, ok()).unwrap();
+        assert_token_active(&deps.storage, &token).unwrap();
+        assert!(!PENDING_REGISTRATIONS.has(&deps.storage, 1));
+        assert!(!PENDING_VIEWING_KEYS.has(&deps.storage, 2));
+    }
+
+    #[test]
+    fn failed_registrations_leave_the_token_pending() {
+        let mut deps = mock_dependencies();
+        let token = token_with_details(&mut deps.storage, TokenStatus::Pending);
+
+        let res =
+            on_token_registered(deps.as_mut(), 1, SubMsgResult::Err("out of gas".into())).unwrap();
+        assert_eq!(res.attributes[0].value, "token_registration_failed");
+        assert_eq!(
+            assert_token_active(&deps.storage, &token).unwrap_err(),
+            ContractError::TokenNotActive {
+                token: "token".into(),
+                status: TokenStatus::Pending,
+            }
+        );
+    }
+
+    #[test]
+    fn only_tokens_with_details_are_flagged_failing() {
+        let mut deps = mock_dependencies();
+        let token = token_with_details(&mut deps.storage, TokenStatus::Active);
+
+        flag_failing_token(&mut deps.storage, &token).unwrap();
+        assert_eq!(
+            TOKEN_DETAILS.load(&deps.storage, &token).unwrap().status,
+            TokenStatus::Failing
+        );
+        // tokens of older deployments have no status to keep
+        let legacy = Addr::unchecked("legacy");
+        flag_failing_token(&mut deps.storage, &legacy).unwrap();
+        assert_token_active(&deps.storage, &legacy).unwrap();
+    }
+
+    #[test]
+    fn failed_viewing_keys_put_the_token_back_to_pending() {
+        let mut deps = mock_dependencies();
+        let token = token_with_details(&mut deps.storage, TokenStatus::Pending);
+
+        on_token_registered(deps.as_mut(), 1, ok()).unwrap();
+        let res =
+            on_viewing_key_set(deps.as_mut(), 2, SubMsgResult::Err("out of gas".into())).unwrap();
+        assert_eq!(res.attributes[0].value, "viewing_key_failed");
+        assert_eq!(
+            TOKEN_DETAILS.load(&deps.storage, &token).unwrap().status,
+            TokenStatus::Pending
+        );
+    }
+}
@@ -0,0 +1,155 @@
+use cosmwasm_std::{Addr, Binary, Env, MessageInfo, StdResult, Storage};
+use secret_toolkit::viewing_key::{ViewingKey, ViewingKeyStore};
+use sha2::{Digest, Sha256};
+
+use crate::state::{BRIDGE_VIEWING_KEY, PRNG_SEED};
+
+/// The key set on the bridge's accounts before keys were generated, in use until rotated
+pub const LEGACY_VIEWING_KEY: &str = "SNIP20-ICS20";
+
+/// The viewing key of the bridge's account with every registered token
+pub fn bridge_viewing_key(storage: &dyn Storage) -> StdResult<String> {
+    Ok(BRIDGE_VIEWING_KEY
+        .may_load(storage)?
+        .unwrap_or_else(|| LEGACY_VIEWING_KEY.into()))
+}
+
+/// Mixes the entropy and the current block into the seed and returns the new seed.
+/// The cosmwasm-std 1.0 we build against has no env.block.random, so the seed only
+/// gains what the callers' entropy adds; mix in the block's randomness once it is available.
+pub fn next_seed(storage: &mut dyn Storage, env: &Env, entropy: &[u8]) -> StdResult<Vec<u8>> {
+    let mut hasher = Sha256::new();
+    hasher.update(PRNG_SEED.may_load(storage)?.unwrap_or_default());
+    hasher.update(entropy);
+    hasher.update(env.block.height.to_be_bytes());
+    hasher.update(env.block.time.nanos().to_be_bytes());
+    hasher.update(env.contract.address.as_bytes());
+    let seed = hasher.finalize().to_vec();
+    PRNG_SEED.save(storage, &seed)?;
+    Ok(seed)
+}
+
+fn derive_key(seed: &[u8], context: &[u8]) -> String {
+    Binary::from(Sha256::new().chain(seed).chain(context).finalize().to_vec()).to_base64()
+}
+
+/// Derives a new bridge viewing key from fresh seed.
+/// The old key stays valid on the tokens until they are re-keyed.
+pub fn new_bridge_viewing_key(
+    storage: &mut dyn Storage,
+    env: &Env,
+    entropy: &[u8],
+) -> StdResult<String> {
+    let seed = next_seed(storage, env, entropy)?;
+    let key = derive_key(&seed, b"viewing_key");
+    BRIDGE_VIEWING_KEY.save(storage, &key)?;
+    Ok(key)
+}
+
+/// Creates a new viewing key for the sender and sets it, replacing any previous one.
+/// Our seed goes into the entropy, so keys are unpredictable without a toolkit seed as well.
+pub fn new_user_viewing_key(
+    storage: &mut dyn Storage,
+    env: &Env,
+    info: &MessageInfo,
+    entropy: &[u8],
+) -> StdResult<String> {
+    let seed = next_seed(storage, env, &[entropy, info.sender.as_bytes()].concat())?;
+    Ok(ViewingKey::create(
+        storage,
+        info,
+        env,
+        info.sender.as_str(),
+        &seed,
+    ))
+}
+
+/// Only a hash of the key is stored
+pub fn set_user_viewing_key(storage: &mut dyn Storage, user: &Addr, key: &str) {
+    ViewingKey::set(storage, user.as_str(), key)
+}
+
+/// Compares the key in constant time, failing alike for a wrong key and no key at all
+pub fn check_user_viewing_key(storage: &dyn Storage, user: &Addr, key: &str) -> StdResult<()> {
+    ViewingKey::check(storage, user.as_str(), key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
+    use cosmwasm_std::{CosmosMsg, WasmMsg};
+
+    use crate::contract::execute;
+    use crate::error::ContractError;
+    use crate::msg::ExecuteMsg;
+    use crate::test_helpers::{add_token, setup, ADMIN};
+
+    fn rotate(entropy: &str) -> ExecuteMsg {
+        ExecuteMsg::RotateViewingKeys {
+            entropy: Some(entropy.into()),
+            padding: None,
+        }
+    }
+
+    fn key_set_on(msg: &CosmosMsg) -> (String, String) {
+        match msg {
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr, msg, ..
+            }) => (
+                contract_addr.clone(),
+                String::from_utf8(msg.to_vec()).unwrap(),
+            ),
+            other => panic!("not a token message: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rotation_sets_a_fresh_key_on_every_token() {
+        let mut deps = mock_dependencies();
+        setup(deps.as_mut());
+        add_token(&mut deps.storage, "token-a");
+        add_token(&mut deps.storage, "token-b");
+        let initial = bridge_viewing_key(&deps.storage).unwrap();
+
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("stranger", &[]),
+            rotate("x"),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized);
+
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADMIN, &[]),
+            rotate("x"),
+        )
+        .unwrap();
+        let key = bridge_viewing_key(&deps.storage).unwrap();
+        assert_ne!(key, initial);
+        assert_ne!(key, LEGACY_VIEWING_KEY);
+        let tokens: Vec<String> = res
+            .messages
+            .iter()
+            .map(|sub| {
+                let (token, msg) = key_set_on(&sub.msg);
+                assert!(msg.contains(&key));
+                token
+            })
+            .collect();
+        assert_eq!(tokens, vec!["token-a", "token-b"]);
+
+        // the same entropy in the same block still moves the seed on
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADMIN, &[]),
+            rotate("x"),
+        )
+        .unwrap();
+        assert_ne!(bridge_viewing_key(&deps.storage).unwrap(), key);
+    }
+}
@@ -177,7 +177,10 @@ beforeAll(async () => {
         sender: accounts1[0].address,
         codeId: contracts.ics20.codeId,
         codeHash: contracts.ics20.codeHash,
-        initMsg: {},
+        initMsg: {
+          allowed_connections: ["connection-0"],
+          allowed_counterparty_ports: ["transfer"],
+        },
         label: `ics20-${Date.now()}`,
       }),
     ],